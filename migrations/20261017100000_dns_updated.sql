@@ -0,0 +1 @@
+ALTER TABLE key_db ADD COLUMN dns_updated BOOLEAN NOT NULL DEFAULT TRUE;
@@ -0,0 +1 @@
+ALTER TABLE key_db ADD COLUMN usable_after INTEGER NOT NULL DEFAULT 0;
@@ -0,0 +1 @@
+ALTER TABLE key_db ADD COLUMN imported BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,2 @@
+ALTER TABLE key_db ADD COLUMN dns_deleted BOOLEAN NOT NULL DEFAULT FALSE;
+UPDATE key_db SET dns_deleted = TRUE WHERE published IS TRUE;
@@ -1,43 +1,58 @@
 use crate::config::Config;
-use crate::entry::read_entry;
+use crate::entry::{read_entry, InputLine};
 use crate::key::key_rotation;
-use crate::message::Message;
+use crate::message::{Message, RejectedMessages};
 use crate::stdin_reader::StdinReader;
 use sqlx::SqlitePool;
 use std::sync::Arc;
+use tokio::signal::unix::Signal;
 use tokio::sync::RwLock;
 use tokio::time::sleep;
 
 pub enum Action<'a> {
 	ReadLine(Arc<RwLock<StdinReader>>),
-	RotateKeys((&'a SqlitePool, &'a Config)),
-	SendMessage((&'a SqlitePool, &'a Config, Message)),
+	ReadSignal(Arc<RwLock<Signal>>),
+	RotateKeys((&'a SqlitePool, Arc<Config>)),
+	RotateKeysNow((&'a SqlitePool, Arc<Config>)),
+	SendMessage((&'a SqlitePool, RejectedMessages, Box<Message>)),
 }
 
 pub enum ActionResult {
 	EndOfStream,
+	Hangup,
 	KeyRotation,
+	KeyRotationNow,
 	MessageSent(String),
 	NewEntry(crate::entry::Entry),
 	NewEntryError(String),
+	NewReport(crate::report::Report),
 }
 
 pub async fn new_action(action: Action<'_>) -> ActionResult {
 	match action {
 		Action::ReadLine(reader_lock) => match read_entry(reader_lock).await {
 			Some(r) => match r {
-				Ok(entry) => ActionResult::NewEntry(entry),
+				Ok(InputLine::Entry(entry)) => ActionResult::NewEntry(entry),
+				Ok(InputLine::Report(report)) => ActionResult::NewReport(report),
 				Err(err) => ActionResult::NewEntryError(err.to_string()),
 			},
 			None => ActionResult::EndOfStream,
 		},
+		Action::ReadSignal(signal_lock) => {
+			signal_lock.write().await.recv().await;
+			ActionResult::Hangup
+		}
 		Action::RotateKeys((db, cnf)) => {
-			let duration = key_rotation(db, cnf).await;
+			let duration = key_rotation(db, &cnf).await;
 			sleep(duration).await;
 			ActionResult::KeyRotation
 		}
-		Action::SendMessage((db, cnf, msg)) => {
-			let msg_id = msg.sign_and_return(db, cnf).await;
+		Action::RotateKeysNow((db, cnf)) => {
+			key_rotation(db, &cnf).await;
+			ActionResult::KeyRotationNow
+		}
+		Action::SendMessage((db, rejected, mut msg)) => {
+			let msg_id = msg.sign_and_return(db, &rejected).await;
 			ActionResult::MessageSent(msg_id)
 		}
 	}
@@ -0,0 +1,310 @@
+use anyhow::{anyhow, Result};
+use nom::branch::alt;
+use nom::bytes::complete::{tag, take, take_while1};
+use nom::combinator::{all_consuming, map, opt, recognize, value};
+use nom::multi::{many0, many1, separated_list1};
+use nom::sequence::{delimited, pair, preceded, tuple};
+use nom::IResult;
+use std::fmt;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mailbox {
+	local_part: String,
+	domain: String,
+}
+
+impl Mailbox {
+	pub fn domain(&self) -> &str {
+		&self.domain
+	}
+}
+
+impl fmt::Display for Mailbox {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}@{}", self.local_part, self.domain)
+	}
+}
+
+/// Parses an RFC 5322 address-list, as found in the From header, and returns every mailbox it
+/// contains, including the members of groups.
+pub fn parse_address_list(input: &[u8]) -> Result<Vec<Mailbox>> {
+	let (_, lst) = all_consuming(address_list)(input).map_err(|_| {
+		anyhow!(
+			"{}: invalid address list",
+			String::from_utf8_lossy(input).trim()
+		)
+	})?;
+	Ok(lst.into_iter().flatten().collect())
+}
+
+/// Parses a single mailbox, either as a bare addr-spec or enclosed in angle brackets.
+pub fn parse_mailbox(input: &[u8]) -> Result<Mailbox> {
+	let (_, mb) = all_consuming(mailbox)(input)
+		.map_err(|_| anyhow!("{}: invalid mailbox", String::from_utf8_lossy(input).trim()))?;
+	Ok(mb)
+}
+
+fn is_wsp(c: u8) -> bool {
+	c == b' ' || c == b'\t'
+}
+
+// Header values still contain the CRLF of folded lines, they are therefore accepted anywhere
+// folding white space is.
+fn is_fws(c: u8) -> bool {
+	is_wsp(c) || c == b'\r' || c == b'\n'
+}
+
+// Bytes above 0x7f are accepted in order to support RFC 6532 internationalized headers.
+fn is_atext(c: u8) -> bool {
+	c.is_ascii_alphanumeric() || b"!#$%&'*+-/=?^_`{|}~".contains(&c) || c >= 0x80
+}
+
+fn is_ctext(c: u8) -> bool {
+	(33..=39).contains(&c) || (42..=91).contains(&c) || (93..=126).contains(&c) || c >= 0x80
+}
+
+fn is_qtext(c: u8) -> bool {
+	c == 33 || (35..=91).contains(&c) || (93..=126).contains(&c) || c >= 0x80
+}
+
+fn is_dtext(c: u8) -> bool {
+	(33..=90).contains(&c) || (94..=126).contains(&c) || c >= 0x80
+}
+
+fn fws(input: &[u8]) -> IResult<&[u8], &[u8]> {
+	take_while1(is_fws)(input)
+}
+
+fn quoted_pair(input: &[u8]) -> IResult<&[u8], &[u8]> {
+	recognize(pair(tag("\\"), take(1usize)))(input)
+}
+
+fn comment(input: &[u8]) -> IResult<&[u8], &[u8]> {
+	recognize(tuple((
+		tag("("),
+		many0(alt((fws, take_while1(is_ctext), quoted_pair, comment))),
+		tag(")"),
+	)))(input)
+}
+
+fn cfws(input: &[u8]) -> IResult<&[u8], &[u8]> {
+	recognize(many1(alt((fws, comment))))(input)
+}
+
+fn atom(input: &[u8]) -> IResult<&[u8], &[u8]> {
+	delimited(opt(cfws), take_while1(is_atext), opt(cfws))(input)
+}
+
+fn dot_atom(input: &[u8]) -> IResult<&[u8], &[u8]> {
+	delimited(
+		opt(cfws),
+		recognize(pair(
+			take_while1(is_atext),
+			many0(pair(tag("."), take_while1(is_atext))),
+		)),
+		opt(cfws),
+	)(input)
+}
+
+fn quoted_string(input: &[u8]) -> IResult<&[u8], &[u8]> {
+	delimited(
+		opt(cfws),
+		recognize(tuple((
+			tag("\""),
+			many0(alt((fws, take_while1(is_qtext), quoted_pair))),
+			tag("\""),
+		))),
+		opt(cfws),
+	)(input)
+}
+
+fn domain_literal(input: &[u8]) -> IResult<&[u8], &[u8]> {
+	delimited(
+		opt(cfws),
+		recognize(tuple((
+			tag("["),
+			many0(alt((fws, take_while1(is_dtext)))),
+			tag("]"),
+		))),
+		opt(cfws),
+	)(input)
+}
+
+fn word(input: &[u8]) -> IResult<&[u8], &[u8]> {
+	alt((atom, quoted_string))(input)
+}
+
+// Periods are allowed in order to support the obsolete phrase syntax (e.g. "John Q. Public").
+// Encoded-words are made of atext characters and are therefore parsed as atoms.
+fn phrase(input: &[u8]) -> IResult<&[u8], &[u8]> {
+	recognize(pair(word, many0(alt((word, tag("."), cfws)))))(input)
+}
+
+fn local_part(input: &[u8]) -> IResult<&[u8], &[u8]> {
+	alt((dot_atom, quoted_string))(input)
+}
+
+fn domain(input: &[u8]) -> IResult<&[u8], &[u8]> {
+	alt((dot_atom, domain_literal))(input)
+}
+
+fn addr_spec(input: &[u8]) -> IResult<&[u8], Mailbox> {
+	map(
+		tuple((local_part, tag("@"), domain)),
+		|(local_part, _, domain)| Mailbox {
+			local_part: String::from_utf8_lossy(local_part).to_string(),
+			domain: String::from_utf8_lossy(domain).to_string(),
+		},
+	)(input)
+}
+
+fn angle_addr(input: &[u8]) -> IResult<&[u8], Mailbox> {
+	delimited(
+		pair(opt(cfws), tag("<")),
+		addr_spec,
+		pair(tag(">"), opt(cfws)),
+	)(input)
+}
+
+fn name_addr(input: &[u8]) -> IResult<&[u8], Mailbox> {
+	preceded(opt(phrase), angle_addr)(input)
+}
+
+fn mailbox(input: &[u8]) -> IResult<&[u8], Mailbox> {
+	alt((name_addr, addr_spec))(input)
+}
+
+fn mailbox_list(input: &[u8]) -> IResult<&[u8], Vec<Mailbox>> {
+	separated_list1(tag(","), mailbox)(input)
+}
+
+fn group(input: &[u8]) -> IResult<&[u8], Vec<Mailbox>> {
+	map(
+		tuple((
+			phrase,
+			tag(":"),
+			opt(alt((mailbox_list, value(Vec::new(), cfws)))),
+			tag(";"),
+			opt(cfws),
+		)),
+		|(_, _, lst, _, _)| lst.unwrap_or_default(),
+	)(input)
+}
+
+fn address(input: &[u8]) -> IResult<&[u8], Vec<Mailbox>> {
+	alt((map(mailbox, |mb| vec![mb]), group))(input)
+}
+
+fn address_list(input: &[u8]) -> IResult<&[u8], Vec<Vec<Mailbox>>> {
+	separated_list1(tag(","), address)(input)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn domains(input: &[u8]) -> Vec<String> {
+		parse_address_list(input)
+			.unwrap()
+			.iter()
+			.map(|mb| mb.domain().to_string())
+			.collect()
+	}
+
+	#[test]
+	fn simple() {
+		assert_eq!(domains(b" john@example.org\r\n"), vec!["example.org"]);
+		assert_eq!(domains(b" <john@example.org>\r\n"), vec!["example.org"]);
+		assert_eq!(
+			domains(b" John Doe <john.doe@example.org>\r\n"),
+			vec!["example.org"]
+		);
+		assert_eq!(
+			domains(b" John Q. Public <john@example.org>\r\n"),
+			vec!["example.org"]
+		);
+	}
+
+	#[test]
+	fn folded() {
+		assert_eq!(
+			domains(b" \"Doe, John\"\r\n\t<john@example.org>\r\n"),
+			vec!["example.org"]
+		);
+	}
+
+	#[test]
+	fn comments() {
+		assert_eq!(
+			domains(b" \"a@b\" <x@example.org> (c@d)\r\n"),
+			vec!["example.org"]
+		);
+		assert_eq!(
+			domains(b" x@example.org (John (the \\) one) @ home)\r\n"),
+			vec!["example.org"]
+		);
+		assert_eq!(
+			domains(b" (c@d) x@(comment)example.org\r\n"),
+			vec!["example.org"]
+		);
+	}
+
+	#[test]
+	fn quoted_local_part() {
+		let lst = parse_address_list(b" \"john@doe\"@example.org\r\n").unwrap();
+		assert_eq!(lst.len(), 1);
+		assert_eq!(lst[0].domain(), "example.org");
+		assert_eq!(lst[0].to_string(), "\"john@doe\"@example.org");
+	}
+
+	#[test]
+	fn encoded_word() {
+		assert_eq!(
+			domains(b" =?UTF-8?Q?Jos=C3=A9_=40_home?= <jose@example.org>\r\n"),
+			vec!["example.org"]
+		);
+	}
+
+	#[test]
+	fn several() {
+		assert_eq!(
+			domains(b" a@example.org, B <b@example.com>\r\n"),
+			vec!["example.org", "example.com"]
+		);
+	}
+
+	#[test]
+	fn groups() {
+		assert_eq!(
+			domains(b" Team: a@example.org, b@example.com;, c@example.net\r\n"),
+			vec!["example.org", "example.com", "example.net"]
+		);
+		assert!(domains(b" undisclosed-recipients:;\r\n").is_empty());
+	}
+
+	#[test]
+	fn domain_literal() {
+		assert_eq!(domains(b" john@[192.0.2.1]\r\n"), vec!["[192.0.2.1]"]);
+	}
+
+	#[test]
+	fn mailbox() {
+		let mb = parse_mailbox(b"john@example.org").unwrap();
+		assert_eq!(mb.domain(), "example.org");
+		assert_eq!(
+			parse_mailbox(b"<john@example.org>").unwrap().domain(),
+			"example.org"
+		);
+		assert!(parse_mailbox(b"").is_err());
+		assert!(parse_mailbox(b"a@example.org, b@example.org").is_err());
+	}
+
+	#[test]
+	fn invalid() {
+		assert!(parse_address_list(b"\r\n").is_err());
+		assert!(parse_address_list(b" john\r\n").is_err());
+		assert!(parse_address_list(b" <john@example.org\r\n").is_err());
+		assert!(parse_address_list(b" john@example.org (unclosed\r\n").is_err());
+		assert!(parse_address_list(b" a@b@example.org\r\n").is_err());
+	}
+}
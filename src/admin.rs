@@ -0,0 +1,898 @@
+use crate::algorithm::{export_jwk, export_pem, export_public_pem, import_private_key, Algorithm};
+use crate::config::Config;
+use anyhow::{anyhow, Result};
+use clap::Subcommand;
+use sqlx::types::time::OffsetDateTime;
+use sqlx::SqlitePool;
+use std::fmt;
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tokio::time::Duration;
+
+type KeyRow = (
+	String,
+	String,
+	String,
+	i64,
+	i64,
+	i64,
+	bool,
+	bool,
+	i64,
+	String,
+	bool,
+);
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+	/// Manage the keys stored in the key database
+	#[command(subcommand)]
+	Keys(KeysCommand),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum KeysCommand {
+	/// List the keys, optionally for a single domain
+	List { domain: Option<String> },
+	/// Show the details of a key
+	Show {
+		selector: String,
+		/// Domain of the key, required if the selector is used by several domains
+		#[arg(long)]
+		domain: Option<String>,
+	},
+	/// Generate a new key for a domain and each signing algorithm
+	Rotate { domain: String },
+	/// Import an existing private key
+	Import {
+		#[arg(long)]
+		selector: String,
+		#[arg(long)]
+		domain: String,
+		/// Number of seconds before the end of the key's cryptoperiod
+		#[arg(long, value_name = "SECONDS", conflicts_with = "not_after")]
+		validity: Option<NonZeroU64>,
+		/// End of the key's cryptoperiod, as a Unix timestamp
+		#[arg(long, value_name = "TIMESTAMP")]
+		not_after: Option<i64>,
+		/// Signing algorithm, required for weak keys
+		#[arg(long)]
+		algorithm: Option<Algorithm>,
+		file: PathBuf,
+	},
+	/// Revoke a key, delete its DNS record and publish it in the revocation list
+	Revoke {
+		selector: String,
+		/// Domain of the key, required if the selector is used by several domains
+		#[arg(long)]
+		domain: Option<String>,
+	},
+	/// Export keys, selected by selector and/or domain
+	Export {
+		#[arg(long)]
+		selector: Option<String>,
+		#[arg(long)]
+		domain: Option<String>,
+		#[arg(long, default_value_t = KeyFormat::default())]
+		format: KeyFormat,
+		/// Only export the public keys
+		#[arg(long)]
+		public_only: bool,
+	},
+	/// Write a consistent copy of the key database
+	Backup { file: PathBuf },
+	/// Print the DNS record of a key
+	DnsRecord {
+		selector: String,
+		/// Domain of the key, required if the selector is used by several domains
+		#[arg(long)]
+		domain: Option<String>,
+		#[arg(long, default_value_t = RecordFormat::default())]
+		format: RecordFormat,
+	},
+	/// Print the DNS records of every active key, optionally for a single domain
+	DnsRecords {
+		domain: Option<String>,
+		#[arg(long, default_value_t = RecordFormat::default())]
+		format: RecordFormat,
+	},
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Validity {
+	Default,
+	Duration(NonZeroU64),
+	NotAfter(i64),
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub enum RecordFormat {
+	Text,
+	#[default]
+	Zone,
+}
+
+impl fmt::Display for RecordFormat {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let s = match self {
+			Self::Text => "text",
+			Self::Zone => "zone",
+		};
+		write!(f, "{s}")
+	}
+}
+
+impl FromStr for RecordFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"text" => Ok(Self::Text),
+			"zone" => Ok(Self::Zone),
+			_ => Err(format!("{s}: invalid record format")),
+		}
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub enum KeyFormat {
+	Jwk,
+	#[default]
+	Pem,
+}
+
+impl fmt::Display for KeyFormat {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let s = match self {
+			Self::Jwk => "jwk",
+			Self::Pem => "pem",
+		};
+		write!(f, "{s}")
+	}
+}
+
+impl FromStr for KeyFormat {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"jwk" => Ok(Self::Jwk),
+			"pem" => Ok(Self::Pem),
+			_ => Err(format!("{s}: invalid key format")),
+		}
+	}
+}
+
+pub async fn run(db: &SqlitePool, cnf: &Config, cmd: &Command) -> Result<()> {
+	let output = match cmd {
+		Command::Keys(KeysCommand::List { domain }) => list_keys(db, domain.as_deref()).await?,
+		Command::Keys(KeysCommand::Show { selector, domain }) => {
+			show_key(db, selector, domain.as_deref()).await?
+		}
+		Command::Keys(KeysCommand::Rotate { domain }) => {
+			rotate_key(db, cnf, domain).await?;
+			String::new()
+		}
+		Command::Keys(KeysCommand::Import {
+			selector,
+			domain,
+			validity,
+			not_after,
+			algorithm,
+			file,
+		}) => {
+			let validity = match (validity, not_after) {
+				(_, Some(ts)) => Validity::NotAfter(*ts),
+				(Some(v), None) => Validity::Duration(*v),
+				(None, None) => Validity::Default,
+			};
+			import_key(db, cnf, selector, domain, validity, *algorithm, file).await?;
+			String::new()
+		}
+		Command::Keys(KeysCommand::Revoke { selector, domain }) => {
+			revoke_key(db, cnf, selector, domain.as_deref()).await?;
+			String::new()
+		}
+		Command::Keys(KeysCommand::Export {
+			selector,
+			domain,
+			format,
+			public_only,
+		}) => {
+			export_keys(
+				db,
+				selector.as_deref(),
+				domain.as_deref(),
+				*format,
+				*public_only,
+			)
+			.await?
+		}
+		Command::Keys(KeysCommand::Backup { file }) => {
+			backup_db(db, file).await?;
+			String::new()
+		}
+		Command::Keys(KeysCommand::DnsRecord {
+			selector,
+			domain,
+			format,
+		}) => print_dns_record(db, cnf, selector, domain.as_deref(), *format).await?,
+		Command::Keys(KeysCommand::DnsRecords { domain, format }) => {
+			print_dns_records(db, cnf, domain.as_deref(), *format).await?
+		}
+	};
+	print!("{output}");
+	Ok(())
+}
+
+async fn list_keys(db: &SqlitePool, domain: Option<&str>) -> Result<String> {
+	let res: Vec<KeyRow> = sqlx::query_as(crate::db::SELECT_KEYS)
+		.bind(domain)
+		.fetch_all(db)
+		.await?;
+	let mut ret =
+		String::from("selector\tsdid\talgorithm\tcreation\tnot_after\trevocation\tstate\n");
+	for row in res {
+		let (selector, sdid, algorithm, creation, not_after, revocation, ..) = &row;
+		ret += &format!(
+			"{selector}\t{sdid}\t{algorithm}\t{}\t{}\t{}\t{}\n",
+			format_ts(*creation)?,
+			format_ts(*not_after)?,
+			format_ts(*revocation)?,
+			key_state(&row),
+		);
+	}
+	Ok(ret)
+}
+
+async fn show_key(db: &SqlitePool, selector: &str, domain: Option<&str>) -> Result<String> {
+	let row = get_key(db, selector, domain).await?;
+	let (
+		selector,
+		sdid,
+		algorithm,
+		creation,
+		not_after,
+		revocation,
+		..,
+		usable_after,
+		public_key,
+		imported,
+	) = &row;
+	let mut ret = format!("selector: {selector}\n");
+	ret += &format!("sdid: {sdid}\n");
+	ret += &format!("algorithm: {algorithm}\n");
+	ret += &format!("creation: {}\n", format_ts(*creation)?);
+	ret += &format!("usable_after: {}\n", format_ts(*usable_after)?);
+	ret += &format!("not_after: {}\n", format_ts(*not_after)?);
+	ret += &format!("revocation: {}\n", format_ts(*revocation)?);
+	ret += &format!("state: {}\n", key_state(&row));
+	ret += &format!("imported: {imported}\n");
+	ret += &format!("public_key: {public_key}\n");
+	Ok(ret)
+}
+
+async fn rotate_key(db: &SqlitePool, cnf: &Config, domain: &str) -> Result<()> {
+	if !cnf.has_dns_update_cmd() {
+		return Err(anyhow!("rotating a key requires --dns-update-cmd"));
+	}
+	let domain = domain.to_lowercase();
+	if cnf.signing_domain(&domain).as_deref() != Some(&domain) {
+		log::warn!("{domain}: domain is not in the configured list");
+	}
+	for algorithm in cnf.algorithms(&domain) {
+		crate::key::generate_key(db, cnf, &domain, *algorithm).await?;
+	}
+	Ok(())
+}
+
+async fn import_key(
+	db: &SqlitePool,
+	cnf: &Config,
+	selector: &str,
+	domain: &str,
+	validity: Validity,
+	algorithm: Option<Algorithm>,
+	file: &Path,
+) -> Result<()> {
+	check_selector(selector)?;
+	let domain = domain.to_lowercase();
+	let data = tokio::fs::read(file)
+		.await
+		.map_err(|e| anyhow!("{}: {e}", file.display()))?;
+	let (key_algorithm, priv_key, pub_key) =
+		import_private_key(&data).map_err(|e| anyhow!("{}: {e}", file.display()))?;
+	let algorithm = match algorithm {
+		Some(algorithm) if algorithm.accepts_key_of(key_algorithm) => algorithm,
+		Some(algorithm) => {
+			return Err(anyhow!(
+				"{}: the key cannot be used with {algorithm}",
+				file.display()
+			))
+		}
+		None if key_algorithm.is_weak() => {
+			return Err(anyhow!(
+				"{}: RSA-{} keys are considered insecure, the signing algorithm must be set explicitly to import them",
+				file.display(),
+				key_algorithm.key_size()
+			))
+		}
+		None => key_algorithm,
+	};
+	if algorithm.is_weak() {
+		log::warn!("{selector}._domainkey.{domain}: {algorithm} is considered insecure and is only meant for receivers which do not support anything else");
+	}
+	let res: Option<(String,)> = sqlx::query_as(crate::db::SELECT_KEY_EXISTS)
+		.bind(selector)
+		.bind(&domain)
+		.fetch_optional(db)
+		.await?;
+	if res.is_some() {
+		return Err(anyhow!(
+			"{selector}._domainkey.{domain}: key already exists"
+		));
+	}
+	let now = OffsetDateTime::now_utc();
+	let not_after = match validity {
+		Validity::Default => now + Duration::from_secs(cnf.cryptoperiod(&domain).get()),
+		Validity::Duration(validity) => now + Duration::from_secs(validity.get()),
+		Validity::NotAfter(ts) => OffsetDateTime::from_unix_timestamp(ts)?,
+	};
+	if not_after <= now {
+		return Err(anyhow!(
+			"{selector}._domainkey.{domain}: the end of the cryptoperiod is in the past"
+		));
+	}
+	let revocation = not_after + Duration::from_secs(cnf.revocation(&domain));
+	sqlx::query(crate::db::INSERT_IMPORTED_KEY)
+		.bind(selector)
+		.bind(&domain)
+		.bind(algorithm.to_string())
+		.bind(now.unix_timestamp())
+		.bind(not_after.unix_timestamp())
+		.bind(revocation.unix_timestamp())
+		.bind(priv_key)
+		.bind(pub_key)
+		.execute(db)
+		.await?;
+	log::info!("{selector}._domainkey.{domain}: {algorithm} key imported, valid until {not_after}");
+	if !cnf.algorithms(&domain).contains(&algorithm) {
+		log::warn!("{selector}._domainkey.{domain}: the key will not be used unless {algorithm} is one of the signing algorithms");
+	}
+	Ok(())
+}
+
+// RFC 6376, section 3.1: a selector is a sequence of DNS labels.
+fn check_selector(selector: &str) -> Result<()> {
+	let is_valid_label = |label: &str| {
+		!label.is_empty()
+			&& label.len() <= 63
+			&& label
+				.bytes()
+				.all(|c| c.is_ascii_alphanumeric() || c == b'-')
+			&& !label.starts_with('-')
+			&& !label.ends_with('-')
+	};
+	if !selector.split('.').all(is_valid_label) {
+		return Err(anyhow!("{selector}: invalid selector"));
+	}
+	Ok(())
+}
+
+async fn revoke_key(
+	db: &SqlitePool,
+	cnf: &Config,
+	selector: &str,
+	domain: Option<&str>,
+) -> Result<()> {
+	let (selector, sdid, ..) = get_key(db, selector, domain).await?;
+	let name = crate::dns::record_name(&selector, &sdid);
+	let res = sqlx::query(crate::db::UPDATE_REVOKE_KEY)
+		.bind(&selector)
+		.bind(&sdid)
+		.execute(db)
+		.await?;
+	if res.rows_affected() == 0 {
+		return Err(anyhow!("{name}: key already published"));
+	}
+	log::info!("{name}: key revoked");
+	crate::key::delete_expired_records(db, cnf).await?;
+	match cnf.revocation_list() {
+		Some(path) => {
+			crate::key::publish_expired_keys(db, path).await?;
+		}
+		None => log::warn!("no revocation list configured, the private key will not be published"),
+	}
+	Ok(())
+}
+
+async fn export_keys(
+	db: &SqlitePool,
+	selector: Option<&str>,
+	domain: Option<&str>,
+	format: KeyFormat,
+	public_only: bool,
+) -> Result<String> {
+	if selector.is_none() && domain.is_none() {
+		return Err(anyhow!("a selector or a domain is required"));
+	}
+	let domain = domain.map(|d| d.to_lowercase());
+	let res: Vec<(String, String, String, String, String)> =
+		sqlx::query_as(crate::db::SELECT_EXPORT_KEYS)
+			.bind(selector)
+			.bind(&domain)
+			.fetch_all(db)
+			.await?;
+	if res.is_empty() {
+		return Err(anyhow!("no matching key found"));
+	}
+	let mut ret = String::new();
+	let mut jwks = Vec::with_capacity(res.len());
+	for (selector, sdid, algorithm, private_key, public_key) in res {
+		let algorithm = algorithm.parse::<Algorithm>().map_err(|e| anyhow!(e))?;
+		let name = crate::dns::record_name(&selector, &sdid);
+		match format {
+			KeyFormat::Jwk => {
+				jwks.push(export_jwk(algorithm, &selector, &private_key, public_only)?);
+			}
+			KeyFormat::Pem => {
+				ret += &format!("# {name} ({algorithm})\n");
+				if public_only {
+					ret += &export_public_pem(algorithm, &public_key)?;
+				} else {
+					let (priv_pem, pub_pem) = export_pem(algorithm, &private_key)?;
+					ret += &format!("{priv_pem}{pub_pem}");
+				}
+			}
+		}
+	}
+	if let KeyFormat::Jwk = format {
+		ret += &format!("{{\"keys\":[{}]}}\n", jwks.join(","));
+	}
+	Ok(ret)
+}
+
+async fn backup_db(db: &SqlitePool, file: &Path) -> Result<()> {
+	if file.exists() {
+		return Err(anyhow!("{}: file already exists", file.display()));
+	}
+	let path = file
+		.to_str()
+		.ok_or(anyhow!("{}: invalid path", file.display()))?;
+	sqlx::query(crate::db::BACKUP_DB)
+		.bind(path)
+		.execute(db)
+		.await?;
+	log::info!("key database saved to {}", file.display());
+	Ok(())
+}
+
+async fn print_dns_record(
+	db: &SqlitePool,
+	cnf: &Config,
+	selector: &str,
+	domain: Option<&str>,
+	format: RecordFormat,
+) -> Result<String> {
+	let (selector, sdid, algorithm, .., public_key, _) = get_key(db, selector, domain).await?;
+	format_record(cnf, &selector, &sdid, &algorithm, &public_key, format)
+}
+
+async fn print_dns_records(
+	db: &SqlitePool,
+	cnf: &Config,
+	domain: Option<&str>,
+	format: RecordFormat,
+) -> Result<String> {
+	let res: Vec<(String, String, String, String)> = sqlx::query_as(crate::db::SELECT_ACTIVE_KEYS)
+		.bind(domain)
+		.fetch_all(db)
+		.await?;
+	let mut ret = String::new();
+	for (selector, sdid, algorithm, public_key) in res {
+		ret += &format_record(cnf, &selector, &sdid, &algorithm, &public_key, format)?;
+	}
+	Ok(ret)
+}
+
+fn format_record(
+	cnf: &Config,
+	selector: &str,
+	sdid: &str,
+	algorithm: &str,
+	public_key: &str,
+	format: RecordFormat,
+) -> Result<String> {
+	let algorithm = algorithm.parse::<Algorithm>().map_err(|e| anyhow!(e))?;
+	let name = crate::dns::record_name(selector, sdid);
+	let txt = crate::dns::txt_record(cnf.txt_record_tags(), algorithm, public_key);
+	let ret = match format {
+		RecordFormat::Text => format!("{name}\t{txt}\n"),
+		RecordFormat::Zone => crate::zone_file::format_record(&name, &txt),
+	};
+	Ok(ret)
+}
+
+async fn get_key(db: &SqlitePool, selector: &str, domain: Option<&str>) -> Result<KeyRow> {
+	let domain = domain.map(|d| d.to_lowercase());
+	let mut res: Vec<KeyRow> = sqlx::query_as(crate::db::SELECT_KEY)
+		.bind(selector)
+		.bind(&domain)
+		.fetch_all(db)
+		.await?;
+	match res.len() {
+		0 => Err(anyhow!("{selector}: key not found")),
+		1 => Ok(res.remove(0)),
+		_ => Err(anyhow!(
+			"{selector}: selector used by several domains, the domain is required"
+		)),
+	}
+}
+
+fn format_ts(ts: i64) -> Result<String> {
+	Ok(OffsetDateTime::from_unix_timestamp(ts)?.to_string())
+}
+
+fn key_state(row: &KeyRow) -> &'static str {
+	let (_, _, _, _, not_after, revocation, published, dns_updated, usable_after, _, _) = *row;
+	let now = OffsetDateTime::now_utc().unix_timestamp();
+	if published {
+		"published"
+	} else if revocation <= now {
+		"revoked"
+	} else if not_after <= now {
+		"expired"
+	} else if !dns_updated {
+		"pending-dns"
+	} else if usable_after > now {
+		"pending"
+	} else {
+		"active"
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	struct TestDir(PathBuf);
+
+	impl TestDir {
+		async fn new() -> Self {
+			let path = std::env::temp_dir().join(format!(
+				"{}-test-{}",
+				env!("CARGO_PKG_NAME"),
+				uuid::Uuid::new_v4().simple()
+			));
+			tokio::fs::create_dir(&path).await.unwrap();
+			Self(path)
+		}
+
+		fn path(&self, name: &str) -> PathBuf {
+			self.0.join(name)
+		}
+	}
+
+	impl Drop for TestDir {
+		fn drop(&mut self) {
+			let _ = std::fs::remove_dir_all(&self.0);
+		}
+	}
+
+	async fn write_key_file(dir: &TestDir, algorithm: Algorithm) -> (PathBuf, String) {
+		let (priv_key, _) = algorithm.gen_keys();
+		let (priv_pem, _) = export_pem(algorithm, &priv_key).unwrap();
+		let path = dir.path("key.pem");
+		tokio::fs::write(&path, priv_pem).await.unwrap();
+		(path, priv_key)
+	}
+
+	#[tokio::test]
+	async fn import_list_show() {
+		let dir = TestDir::new().await;
+		let db = crate::db::init_test_db().await;
+		let cnf = Config::from_args(&["-a", "ed25519-sha256", "-d", "example.org"]);
+		let (path, _) = write_key_file(&dir, Algorithm::Ed25519Sha256).await;
+		import_key(
+			&db,
+			&cnf,
+			"imported",
+			"Example.org",
+			Validity::Default,
+			None,
+			&path,
+		)
+		.await
+		.unwrap();
+		assert!(import_key(
+			&db,
+			&cnf,
+			"imported",
+			"example.org",
+			Validity::Default,
+			None,
+			&path
+		)
+		.await
+		.is_err());
+
+		let output = list_keys(&db, None).await.unwrap();
+		let lines: Vec<&str> = output.lines().collect();
+		assert_eq!(lines.len(), 2);
+		assert!(lines[1].starts_with("imported\texample.org\ted25519-sha256\t"));
+		assert!(lines[1].ends_with("\tactive"));
+		assert_eq!(
+			list_keys(&db, Some("example.com"))
+				.await
+				.unwrap()
+				.lines()
+				.count(),
+			1
+		);
+
+		let output = show_key(&db, "imported", None).await.unwrap();
+		assert!(output.contains("state: active\n"));
+		assert!(output.contains("imported: true\n"));
+		assert!(show_key(&db, "unknown", None).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn import_weak_key() {
+		let dir = TestDir::new().await;
+		let db = crate::db::init_test_db().await;
+		let cnf = Config::from_args(&["-a", "rsa1024-sha1", "-d", "example.org"]);
+		let (path, _) = write_key_file(&dir, Algorithm::Rsa1024Sha256).await;
+		let import = |selector, algorithm| {
+			import_key(
+				&db,
+				&cnf,
+				selector,
+				"example.org",
+				Validity::Default,
+				algorithm,
+				&path,
+			)
+		};
+		assert!(import("s1", None).await.is_err());
+		assert!(import("s1", Some(Algorithm::Rsa2048Sha1)).await.is_err());
+		assert!(import("s1", Some(Algorithm::Ed25519Sha256)).await.is_err());
+		import("s1", Some(Algorithm::Rsa1024Sha1)).await.unwrap();
+		let output = show_key(&db, "s1", None).await.unwrap();
+		assert!(output.contains("algorithm: rsa1024-sha1\n"));
+	}
+
+	#[tokio::test]
+	async fn import_validity() {
+		let dir = TestDir::new().await;
+		let db = crate::db::init_test_db().await;
+		let cnf = Config::from_args(&["-a", "ed25519-sha256", "-d", "example.org"]);
+		let (path, _) = write_key_file(&dir, Algorithm::Ed25519Sha256).await;
+		for selector in ["", "a..b", "-a", "a-", "a_b", "a b", &"a".repeat(64)] {
+			assert!(import_key(
+				&db,
+				&cnf,
+				selector,
+				"example.org",
+				Validity::Default,
+				None,
+				&path
+			)
+			.await
+			.is_err());
+		}
+		let past = OffsetDateTime::now_utc().unix_timestamp() - 1;
+		assert!(import_key(
+			&db,
+			&cnf,
+			"past",
+			"example.org",
+			Validity::NotAfter(past),
+			None,
+			&path
+		)
+		.await
+		.is_err());
+
+		let not_after = OffsetDateTime::now_utc().unix_timestamp() + 86400;
+		import_key(
+			&db,
+			&cnf,
+			"s1.2024",
+			"example.org",
+			Validity::NotAfter(not_after),
+			None,
+			&path,
+		)
+		.await
+		.unwrap();
+		let output = show_key(&db, "s1.2024", None).await.unwrap();
+		assert!(output.contains(&format!("not_after: {}\n", format_ts(not_after).unwrap())));
+	}
+
+	#[tokio::test]
+	async fn revoke_and_publish() {
+		let dir = TestDir::new().await;
+		let db = crate::db::init_test_db().await;
+		let revocation_list = dir.path("revocation-list");
+		let cnf = Config::from_args(&[
+			"-a",
+			"ed25519-sha256",
+			"-d",
+			"example.org",
+			"-u",
+			"builtin:none",
+			"-f",
+			revocation_list.to_str().unwrap(),
+		]);
+		let (path, priv_key) = write_key_file(&dir, Algorithm::Ed25519Sha256).await;
+		import_key(
+			&db,
+			&cnf,
+			"imported",
+			"example.org",
+			Validity::Default,
+			None,
+			&path,
+		)
+		.await
+		.unwrap();
+
+		revoke_key(&db, &cnf, "imported", None).await.unwrap();
+		let content = tokio::fs::read_to_string(&revocation_list).await.unwrap();
+		assert_eq!(
+			content,
+			format!("ed25519 {priv_key} imported._domainkey.example.org\n")
+		);
+		let output = show_key(&db, "imported", None).await.unwrap();
+		assert!(output.contains("state: published\n"));
+		assert!(print_dns_records(&db, &cnf, None, RecordFormat::Text)
+			.await
+			.unwrap()
+			.is_empty());
+		assert!(revoke_key(&db, &cnf, "imported", None).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn revoke_shared_selector() {
+		let dir = TestDir::new().await;
+		let db = crate::db::init_test_db().await;
+		let revocation_list = dir.path("revocation-list");
+		let cnf = Config::from_args(&[
+			"-a",
+			"ed25519-sha256",
+			"-d",
+			"example.org",
+			"-d",
+			"example.com",
+			"-u",
+			"builtin:none",
+			"-f",
+			revocation_list.to_str().unwrap(),
+		]);
+		let (path, priv_key) = write_key_file(&dir, Algorithm::Ed25519Sha256).await;
+		for domain in ["example.org", "example.com"] {
+			import_key(&db, &cnf, "default", domain, Validity::Default, None, &path)
+				.await
+				.unwrap();
+		}
+
+		assert!(revoke_key(&db, &cnf, "default", None).await.is_err());
+		assert!(show_key(&db, "default", None).await.is_err());
+		assert!(
+			print_dns_record(&db, &cnf, "default", None, RecordFormat::Text)
+				.await
+				.is_err()
+		);
+		revoke_key(&db, &cnf, "default", Some("Example.org"))
+			.await
+			.unwrap();
+		let content = tokio::fs::read_to_string(&revocation_list).await.unwrap();
+		assert_eq!(
+			content,
+			format!("ed25519 {priv_key} default._domainkey.example.org\n")
+		);
+		let output = show_key(&db, "default", Some("example.org")).await.unwrap();
+		assert!(output.contains("state: published\n"));
+		let output = show_key(&db, "default", Some("example.com")).await.unwrap();
+		assert!(output.contains("state: active\n"));
+		let records = print_dns_records(&db, &cnf, None, RecordFormat::Text)
+			.await
+			.unwrap();
+		assert!(records.starts_with("default._domainkey.example.com\t"));
+		assert_eq!(records.lines().count(), 1);
+		assert!(print_dns_record(
+			&db,
+			&cnf,
+			"default",
+			Some("example.com"),
+			RecordFormat::Text
+		)
+		.await
+		.is_ok());
+	}
+
+	#[tokio::test]
+	async fn export() {
+		let dir = TestDir::new().await;
+		let db = crate::db::init_test_db().await;
+		let cnf = Config::from_args(&["-a", "ed25519-sha256", "-d", "example.org"]);
+		let (path, _) = write_key_file(&dir, Algorithm::Ed25519Sha256).await;
+		import_key(
+			&db,
+			&cnf,
+			"imported",
+			"example.org",
+			Validity::Default,
+			None,
+			&path,
+		)
+		.await
+		.unwrap();
+		let key_file = tokio::fs::read_to_string(&path).await.unwrap();
+
+		let output = export_keys(&db, Some("imported"), None, KeyFormat::Pem, false)
+			.await
+			.unwrap();
+		assert!(output.starts_with("# imported._domainkey.example.org (ed25519-sha256)\n"));
+		assert!(output.contains(&key_file));
+		assert!(output.contains("-----BEGIN PUBLIC KEY-----"));
+
+		let output = export_keys(&db, None, Some("example.org"), KeyFormat::Pem, true)
+			.await
+			.unwrap();
+		assert!(!output.contains("PRIVATE KEY"));
+		assert!(output.contains("-----BEGIN PUBLIC KEY-----"));
+
+		let output = export_keys(&db, None, Some("example.org"), KeyFormat::Jwk, true)
+			.await
+			.unwrap();
+		assert!(output.starts_with("{\"keys\":[{"));
+		assert!(output.contains("\"kid\":\"imported\""));
+		assert!(!output.contains("\"d\":"));
+
+		assert!(export_keys(&db, None, None, KeyFormat::Pem, false)
+			.await
+			.is_err());
+		assert!(
+			export_keys(&db, None, Some("example.com"), KeyFormat::Pem, false)
+				.await
+				.is_err()
+		);
+	}
+
+	#[tokio::test]
+	async fn backup() {
+		let dir = TestDir::new().await;
+		// The backup of an in-memory database would also be in memory.
+		let db_path = dir.path("key-db.sqlite3");
+		let cnf = Config::from_args(&[
+			"-a",
+			"ed25519-sha256",
+			"-d",
+			"example.org",
+			"-b",
+			db_path.to_str().unwrap(),
+		]);
+		let db = crate::db::init(&cnf).await.unwrap();
+		let (path, _) = write_key_file(&dir, Algorithm::Ed25519Sha256).await;
+		import_key(
+			&db,
+			&cnf,
+			"imported",
+			"example.org",
+			Validity::Default,
+			None,
+			&path,
+		)
+		.await
+		.unwrap();
+		let backup_path = dir.path("backup.sqlite3");
+		backup_db(&db, &backup_path).await.unwrap();
+		assert!(backup_db(&db, &backup_path).await.is_err());
+		let backup_cnf = Config::from_args(&["-b", backup_path.to_str().unwrap()]);
+		let backup = crate::db::init(&backup_cnf).await.unwrap();
+		let output = list_keys(&backup, None).await.unwrap();
+		assert!(output.lines().nth(1).unwrap().starts_with("imported\t"));
+	}
+}
@@ -1,17 +1,21 @@
+use serde::{de, Deserialize, Deserializer};
+use sha2::digest::Update;
+use std::fmt;
 use std::str::FromStr;
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum CanonicalizationType {
 	Relaxed,
 	Simple,
 }
 
-impl ToString for CanonicalizationType {
-	fn to_string(&self) -> String {
-		match self {
-			CanonicalizationType::Relaxed => String::from("relaxed"),
-			CanonicalizationType::Simple => String::from("simple"),
-		}
+impl fmt::Display for CanonicalizationType {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let s = match self {
+			CanonicalizationType::Relaxed => "relaxed",
+			CanonicalizationType::Simple => "simple",
+		};
+		write!(f, "{s}")
 	}
 }
 
@@ -35,17 +39,34 @@ pub struct Canonicalization {
 
 impl Canonicalization {
 	pub fn process_header(&self, header: &[u8]) -> Vec<u8> {
+		let mut ret = VecWriter::default();
+		self.write_header(header, &mut ret);
+		ret.0
+	}
+
+	pub fn write_header(&self, header: &[u8], out: &mut impl Update) {
 		match self.header_alg {
-			CanonicalizationType::Relaxed => header_relaxed(header),
-			CanonicalizationType::Simple => header_simple(header),
+			CanonicalizationType::Relaxed => header_relaxed(header, out),
+			CanonicalizationType::Simple => header_simple(header, out),
 		}
 	}
 
+	pub fn body_alg(&self) -> CanonicalizationType {
+		self.body_alg
+	}
+
+	#[cfg(test)]
 	pub fn process_body(&self, body: &[u8]) -> Vec<u8> {
-		match self.body_alg {
-			CanonicalizationType::Relaxed => body_relaxed(body),
-			CanonicalizationType::Simple => body_simple(body),
+		let mut canonicalizer = BodyCanonicalizer::new(self.body_alg);
+		let mut ret = VecWriter::default();
+		let body = body.strip_suffix(b"\n").unwrap_or(body);
+		if !body.is_empty() {
+			for line in body.split(|&c| c == b'\n') {
+				canonicalizer.process_line(line, &mut ret);
+			}
 		}
+		canonicalizer.finish(&mut ret);
+		ret.0
 	}
 
 	#[cfg(test)]
@@ -70,13 +91,9 @@ impl Default for Canonicalization {
 	}
 }
 
-impl ToString for Canonicalization {
-	fn to_string(&self) -> String {
-		format!(
-			"{}/{}",
-			self.header_alg.to_string(),
-			self.body_alg.to_string()
-		)
+impl fmt::Display for Canonicalization {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}/{}", self.header_alg, self.body_alg)
 	}
 }
 
@@ -95,156 +112,165 @@ impl FromStr for Canonicalization {
 	}
 }
 
-fn to_lowercase(c: &mut u8) {
-	if *c >= b'A' && *c <= b'Z' {
-		*c += 32
+impl<'de> Deserialize<'de> for Canonicalization {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		String::deserialize(deserializer)?
+			.parse()
+			.map_err(de::Error::custom)
 	}
 }
 
-fn to_space(c: &mut u8) {
-	if *c == b'\t' {
-		*c = b' '
-	}
+fn is_wsp(c: &u8) -> bool {
+	*c == b' ' || *c == b'\t'
 }
 
-fn header_relaxed(data: &[u8]) -> Vec<u8> {
-	let mut data = data.to_vec();
-
-	// Step 1: Convert all header field names (not the header field values) to
-	// lowercase. For example, convert "SUBJect: AbC" to "subject: AbC".
-	if let Some(index) = data.iter().position(|&e| e == b':') {
-		data[..index].iter_mut().for_each(to_lowercase);
-	}
-
-	// Step 2: Unfold all header field continuation lines as described in
-	// RFC5322; in particular, lines with terminators embedded in
-	// continued header field values (that is, CRLF sequences followed by
-	// WSP) MUST be interpreted without the CRLF. Implementations MUST
-	// NOT remove the CRLF at the end of the header field value.
-	loop {
-		let last_pos = data.len() - 2;
-		match data.windows(2).position(|w| w == b"\r\n") {
-			Some(pos) => {
-				if pos == last_pos {
-					break;
-				}
-				data.remove(pos + 1);
-				data.remove(pos);
-			}
-			None => {
-				break;
-			}
+fn header_relaxed(data: &[u8], out: &mut impl Update) {
+	// Implementations MUST NOT remove the CRLF at the end of the header field value.
+	let data = data.strip_suffix(b"\r\n").unwrap_or(data);
+	let mut in_name = true;
+	let mut pending_wsp = false;
+	let mut trim_wsp = false;
+	let mut start = 0;
+	let mut i = 0;
+	while i < data.len() {
+		let c = data[i];
+		if c == b'\r' && data.get(i + 1) == Some(&b'\n') {
+			// Unfold all header field continuation lines as described in RFC5322.
+			out.update(&data[start..i]);
+			i += 2;
+			start = i;
+			continue;
 		}
-	}
-
-	// Step 3: Convert all sequences of one or more WSP characters to a single SP
-	// character. WSP characters here include those before and after a
-	// line folding boundary.
-	data.iter_mut().for_each(to_space);
-	while let Some(pos) = data.windows(2).position(|w| w == b"  ") {
-		data.remove(pos);
-	}
-
-	// Step 4: Delete the SP character, if present, at the end of each unfolded
-	// header field value before its final CRLF.
-	//
-	// Note: this if from errata 5839
-	loop {
-		let pos = data.len() - 3;
-		if data[pos] != b' ' {
-			break;
+		if is_wsp(&c) {
+			// Convert all sequences of one or more WSP characters to a single SP character.
+			// WSP characters here include those before and after a line folding boundary.
+			// Pending WSP characters are only written once followed by another character, hence
+			// trailing WSP characters are deleted (errata 5839).
+			out.update(&data[start..i]);
+			pending_wsp = !trim_wsp;
+			i += 1;
+			start = i;
+			continue;
+		}
+		if in_name && c == b':' {
+			// Delete any WSP characters remaining before and after the colon separating the
+			// header field name from the header field value.
+			out.update(&data[start..i]);
+			out.update(b":");
+			in_name = false;
+			pending_wsp = false;
+			trim_wsp = true;
+			i += 1;
+			start = i;
+			continue;
 		}
-		data.remove(pos);
-	}
-
-	// Step 5: Delete any WSP characters remaining before and after the colon
-	// separating the header field name from the header field value. The
-	// colon separator MUST be retained.
-	while let Some(pos) = data.iter().position(|&e| e == b':') {
-		if data[pos + 1] == b' ' {
-			data.remove(pos + 1);
-		} else if data[pos - 1] == b' ' {
-			data.remove(pos - 1);
-		} else {
-			break;
+		if pending_wsp {
+			out.update(b" ");
+			pending_wsp = false;
 		}
+		trim_wsp = false;
+		if in_name && c.is_ascii_uppercase() {
+			// Convert all header field names (not the header field values) to lowercase.
+			out.update(&data[start..i]);
+			out.update(&[c.to_ascii_lowercase()]);
+			i += 1;
+			start = i;
+			continue;
+		}
+		i += 1;
 	}
+	out.update(&data[start..]);
+	out.update(b"\r\n");
+}
 
-	data
+fn header_simple(data: &[u8], out: &mut impl Update) {
+	out.update(data);
 }
 
-fn header_simple(data: &[u8]) -> Vec<u8> {
-	data.to_vec()
+/// Canonicalizes the body one line at a time so it can be hashed while it is being received.
+#[derive(Clone, Debug)]
+pub struct BodyCanonicalizer {
+	alg: CanonicalizationType,
+	nb_lines: usize,
+	nb_pending_empty_lines: usize,
+	has_content: bool,
 }
 
-fn body_relaxed(data: &[u8]) -> Vec<u8> {
-	let mut data = data.to_vec();
+impl BodyCanonicalizer {
+	pub fn new(alg: CanonicalizationType) -> Self {
+		Self {
+			alg,
+			nb_lines: 0,
+			nb_pending_empty_lines: 0,
+			has_content: false,
+		}
+	}
+
+	/// Processes a body line, without its line terminator.
+	pub fn process_line(&mut self, line: &[u8], out: &mut impl Update) {
+		let line = line.strip_suffix(b"\r").unwrap_or(line);
 
-	// RFC 5321, section 4.5.2
-	rm_dot_escape(&mut data);
+		// RFC 5321, section 4.5.2
+		let line = line.strip_prefix(b".").unwrap_or(line);
 
-	// Ignore all whitespace at the end of lines.
-	while let Some(pos) = data
-		.windows(3)
-		.position(|w| w == b" \r\n" || w == b"\t\r\n")
-	{
-		data.remove(pos);
-	}
+		self.nb_lines += 1;
 
-	// Reduce all sequences of WSP within a line to a single SP character.
-	while let Some(pos) = data
-		.windows(2)
-		.position(|w| (w[0] == b' ' || w[0] == b'\t') && (w[1] == b' ' || w[1] == b'\t'))
-	{
-		data[pos] = b' ';
-		data.remove(pos + 1);
+		// Ignore all empty lines at the end of the message body: empty lines are only written
+		// once a non-empty line follows them.
+		let is_empty = match self.alg {
+			CanonicalizationType::Relaxed => line.iter().all(is_wsp),
+			CanonicalizationType::Simple => line.is_empty(),
+		};
+		if is_empty {
+			self.nb_pending_empty_lines += 1;
+			return;
+		}
+		for _ in 0..self.nb_pending_empty_lines {
+			out.update(b"\r\n");
+		}
+		self.nb_pending_empty_lines = 0;
+		match self.alg {
+			CanonicalizationType::Relaxed => body_relaxed_line(line, out),
+			CanonicalizationType::Simple => out.update(line),
+		}
+		out.update(b"\r\n");
+		self.has_content = true;
 	}
 
-	// Ignore all empty lines at the end of the message body.
-	while data.ends_with(b"\r\n\r\n") {
-		let pos = data.len();
-		data.remove(pos - 1);
-		data.remove(pos - 2);
+	pub fn finish(&mut self, out: &mut impl Update) {
+		if self.nb_lines != 0 && !self.has_content {
+			out.update(b"\r\n");
+		}
 	}
-
-	data
 }
 
-fn body_simple(data: &[u8]) -> Vec<u8> {
-	let mut data = data.to_vec();
-
-	// RFC 5321, section 4.5.2
-	rm_dot_escape(&mut data);
-
-	// Ignore all empty lines at the end of the message body.
-	while data.ends_with(b"\r\n\r\n") {
-		let pos = data.len();
-		data.remove(pos - 1);
-		data.remove(pos - 2);
+fn body_relaxed_line(line: &[u8], out: &mut impl Update) {
+	// Reduce all sequences of WSP within a line to a single SP character. Since a sequence of WSP
+	// is only written when followed by another character, whitespace at the end of the line is
+	// ignored.
+	for (i, word) in line.split(is_wsp).enumerate() {
+		if !word.is_empty() {
+			if i != 0 {
+				out.update(b" ");
+			}
+			out.update(word);
+		}
 	}
-
-	data
 }
 
-fn rm_dot_escape(data: &mut Vec<u8>) {
-	let mut positions = Vec::with_capacity(1024);
-	let mut data_it = data.windows(3);
-	while let Some(pos) = data_it.position(|w| w == b"\r\n.") {
-		let pos_abs = match positions.last() {
-			Some(last_pos) => last_pos + pos + 1,
-			None => pos + 2,
-		};
-		positions.push(pos_abs);
-	}
-	positions.reverse();
-	for pos in positions {
-		data.remove(pos);
+#[derive(Default)]
+struct VecWriter(Vec<u8>);
+
+impl Update for VecWriter {
+	fn update(&mut self, data: &[u8]) {
+		self.0.extend_from_slice(data);
 	}
 }
 
 #[cfg(test)]
 mod tests {
 	use super::*;
+	use sha2::{Digest, Sha256};
 
 	const HEADER_00: &[u8] = b"Accept-Language:\r\n";
 	const HEADER_01: &[u8] = b"Accept-Language: fr-FR, en-US\r\n";
@@ -477,4 +503,29 @@ mod tests {
 			b"Hello, World!\r\n.\r\n.....plop\r\n..test\r\n.re-test\r\n"
 		);
 	}
+
+	// Canonicalizing line by line into the hasher must give the same result as hashing the
+	// canonicalized message.
+	#[test]
+	fn streaming_hash() {
+		for alg in [CanonicalizationType::Simple, CanonicalizationType::Relaxed] {
+			for body in [BODY_00, BODY_01, BODY_02, BODY_03] {
+				let c = Canonicalization::default().set_body_alg(alg);
+				let mut canonicalizer = BodyCanonicalizer::new(alg);
+				let mut hasher = Sha256::new();
+				let body = body.strip_suffix(b"\n").unwrap();
+				for line in body.split(|&c| c == b'\n') {
+					canonicalizer.process_line(line, &mut hasher);
+				}
+				canonicalizer.finish(&mut hasher);
+				assert_eq!(hasher.finalize(), Sha256::digest(c.process_body(body)));
+			}
+		}
+		for header in [HEADER_05, HEADER_06, HEADER_08, HEADER_10] {
+			let c = Canonicalization::default().set_header_alg(CanonicalizationType::Relaxed);
+			let mut hasher = Sha256::new();
+			header_relaxed(header, &mut hasher);
+			assert_eq!(hasher.finalize(), Sha256::digest(c.process_header(header)));
+		}
+	}
 }
@@ -1,8 +1,17 @@
+use crate::admin::Command;
 use crate::algorithm::Algorithm;
 use crate::canonicalization::Canonicalization;
+use crate::config_file::FileConfig;
+use crate::dns::{DnsUpdateCmd, TxtRecordTags};
+use crate::domain_config::DomainConfig;
+use crate::rfc2136::Rfc2136Config;
+use crate::sdid::{AuidPolicy, SdidSource, SubdomainPolicy};
+use crate::session::{IpNetwork, SignPolicy, DEFAULT_LOCAL_NETWORKS};
 use anyhow::{anyhow, Result};
-use clap::Parser;
-use std::collections::HashSet;
+use base64::{engine::general_purpose, Engine as _};
+use clap::parser::ValueSource;
+use clap::{ArgAction, ArgMatches, CommandFactory, FromArgMatches, Parser};
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::{BufRead, BufReader};
 use std::num::NonZeroU64;
@@ -11,60 +20,403 @@ use std::path::{Path, PathBuf};
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Config {
-	#[arg(short, long, default_value_t = Algorithm::default())]
-	algorithm: Algorithm,
+	#[command(subcommand)]
+	command: Option<Command>,
+	#[arg(long, value_name = "FILE")]
+	config: Option<PathBuf>,
+	#[arg(long)]
+	check_config: bool,
+	#[arg(short, long)]
+	algorithm: Vec<Algorithm>,
+	#[arg(long, value_name = "POLICY", default_value_t = AuidPolicy::default())]
+	auid: AuidPolicy,
+	#[arg(
+		long,
+		num_args = 0..=1,
+		require_equals = true,
+		default_value_t = false,
+		default_missing_value = "true",
+		action = ArgAction::Set
+	)]
+	body_length: bool,
 	#[arg(short = 'b', long, value_name = "FILE")]
 	key_data_base: Option<PathBuf>,
 	#[arg(short, long, default_value_t = Canonicalization::default())]
 	canonicalization: Canonicalization,
+	#[arg(
+		long,
+		num_args = 0..=1,
+		require_equals = true,
+		default_value_t = false,
+		default_missing_value = "true",
+		action = ArgAction::Set
+	)]
+	copy_headers: bool,
 	#[arg(short, long)]
 	domain: Vec<String>,
 	#[arg(short = 'D', long, value_name = "FILE")]
 	domain_file: Option<PathBuf>,
+	#[arg(skip)]
+	domain_config: HashMap<String, DomainConfig>,
 	#[arg(short = 'f', long, value_name = "FILE")]
 	revocation_list: Option<PathBuf>,
 	#[arg(short, long)]
 	header: Vec<String>,
 	#[arg(short = 'o', long)]
 	header_optional: Vec<String>,
+	#[arg(long)]
+	header_oversign: Vec<String>,
 	#[arg(short = 'p', long, default_value_t = NonZeroU64::new(crate::DEFAULT_CNF_CRYPTOPERIOD).unwrap())]
 	cryptoperiod: NonZeroU64,
 	#[arg(short, long, default_value_t = crate::DEFAULT_CNF_REVOCATION)]
 	revocation: u64,
+	#[arg(short = 'w', long, default_value_t = crate::DEFAULT_CNF_PUBLISH_DELAY)]
+	publish_delay: u64,
 	#[arg(short = 'u', long)]
-	dns_update_cmd: String,
+	dns_update_cmd: Option<DnsUpdateCmd>,
+	#[command(flatten)]
+	txt_record_tags: TxtRecordTags,
+	#[arg(long, value_name = "ADDRESS")]
+	rfc2136_server: Option<String>,
+	#[arg(long, value_name = "NAME")]
+	rfc2136_tsig_name: Option<String>,
+	#[arg(long, value_name = "FILE")]
+	rfc2136_tsig_secret_file: Option<PathBuf>,
+	#[arg(skip)]
+	rfc2136: Option<Rfc2136Config>,
+	#[arg(long, value_name = "DIR")]
+	zone_file_dir: Option<PathBuf>,
+	#[arg(long, value_name = "STRING")]
+	zone_file_reload_cmd: Option<String>,
 	#[arg(short, long, action = clap::ArgAction::Count)]
 	verbose: u8,
 	#[arg(short = 'x', long, default_value_t = crate::DEFAULT_CNF_EXPIRATION)]
 	expiration: u64,
+	#[arg(long, value_name = "UINT", default_value_t = crate::DEFAULT_CNF_FOLDING_WIDTH)]
+	folding_width: usize,
+	#[arg(long, default_value_t = SignPolicy::default())]
+	sign_policy: SignPolicy,
+	#[arg(long, value_name = "NETWORK")]
+	local_network: Vec<IpNetwork>,
+	#[arg(long, value_name = "SOURCE")]
+	sdid_source: Vec<SdidSource>,
+	#[arg(long, value_name = "UINT", default_value_t = crate::DEFAULT_CNF_SPOOL_THRESHOLD)]
+	spool_threshold: usize,
+	#[arg(long, value_name = "POLICY", default_value_t = SubdomainPolicy::default())]
+	subdomains: SubdomainPolicy,
+	#[arg(long, value_name = "STRING")]
+	own_subdomain: Vec<String>,
 }
 
 impl Config {
 	pub fn init() -> Result<Self> {
-		let mut cnf = Self::parse();
+		let matches = <Self as CommandFactory>::command().get_matches();
+		let mut cnf = Self::from_arg_matches(&matches)?;
+		let mut file_domains = HashMap::new();
+		if let Some(path) = cnf.config.clone() {
+			let file_cnf = FileConfig::load(&path)?;
+			file_domains = cnf.merge_file_config(&matches, file_cnf);
+		}
+		if cnf.command.is_none() && cnf.dns_update_cmd.is_none() {
+			return Err(anyhow!("the --dns-update-cmd option is required"));
+		}
+		cnf.algorithm = process_algorithms(&cnf.algorithm);
+		cnf.sdid_source = process_sdid_sources(&cnf.sdid_source);
 		cnf.key_data_base = process_key_data_base(cnf.key_data_base);
-		cnf.domain = process_domains(&cnf.domain, &cnf.domain_file)?;
+		(cnf.domain, cnf.domain_config) =
+			process_domains(&cnf.domain, &cnf.domain_file, file_domains)?;
+		cnf.own_subdomain = cnf.process_own_subdomains()?;
+		if cnf.local_network.is_empty() {
+			cnf.local_network = DEFAULT_LOCAL_NETWORKS
+				.iter()
+				.map(|n| n.parse().unwrap())
+				.collect();
+		}
 		cnf.header = process_headers(&cnf.header, crate::DEFAULT_CNF_HEADERS);
 		cnf.header_optional = process_headers(&cnf.header_optional, crate::DEFAULT_CNF_HEADERS_OPT);
+		cnf.header_oversign =
+			process_headers(&cnf.header_oversign, crate::DEFAULT_CNF_HEADERS_OVERSIGN);
+		if let Some(DnsUpdateCmd::Rfc2136) = cnf.dns_update_cmd {
+			cnf.rfc2136 = Some(process_rfc2136(
+				&cnf.rfc2136_server,
+				&cnf.rfc2136_tsig_name,
+				&cnf.rfc2136_tsig_secret_file,
+			)?);
+		}
+		if let Some(DnsUpdateCmd::ZoneFile) = cnf.dns_update_cmd {
+			if cnf.zone_file_dir.is_none() {
+				return Err(anyhow!("builtin:zonefile requires --zone-file-dir"));
+			}
+		}
+		if !(crate::FOLDING_WIDTH_MIN..=crate::FOLDING_WIDTH_MAX).contains(&cnf.folding_width) {
+			return Err(anyhow!(
+				"{}: the folding width must be between {} and {}",
+				cnf.folding_width,
+				crate::FOLDING_WIDTH_MIN,
+				crate::FOLDING_WIDTH_MAX
+			));
+		}
+		for domain in &cnf.domain {
+			if let AuidPolicy::Fixed(auid) = cnf.auid(domain) {
+				crate::sdid::check_auid(auid, domain)?;
+			}
+		}
 		Ok(cnf)
 	}
 
-	pub fn algorithm(&self) -> Algorithm {
-		self.algorithm
+	#[cfg(test)]
+	pub fn from_args(args: &[&str]) -> Self {
+		let mut cnf = Self::try_parse_from([&["filter-dkimout"], args].concat()).unwrap();
+		cnf.algorithm = process_algorithms(&cnf.algorithm);
+		cnf.sdid_source = process_sdid_sources(&cnf.sdid_source);
+		(cnf.domain, cnf.domain_config) =
+			process_domains(&cnf.domain, &None, HashMap::new()).unwrap();
+		cnf.own_subdomain = cnf.process_own_subdomains().unwrap();
+		cnf
+	}
+
+	fn process_own_subdomains(&self) -> Result<Vec<String>> {
+		let mut lst = Vec::with_capacity(self.own_subdomain.len());
+		for domain in &self.own_subdomain {
+			let domain = domain.to_lowercase();
+			if self.domain.contains(&domain) {
+				return Err(anyhow!(
+					"{domain}: the subdomain is already a configured domain"
+				));
+			}
+			match self.parent_domain(&domain) {
+				Some(parent) if self.subdomains(parent) == SubdomainPolicy::Own => {}
+				_ => {
+					return Err(anyhow!(
+						"{domain}: the subdomain does not have a configured parent domain with the own subdomain policy"
+					))
+				}
+			}
+			if !lst.contains(&domain) {
+				lst.push(domain);
+			}
+		}
+		Ok(lst)
+	}
+
+	fn merge_file_config(
+		&mut self,
+		matches: &ArgMatches,
+		file_cnf: FileConfig,
+	) -> HashMap<String, DomainConfig> {
+		let from_cli = |id: &str| matches.value_source(id) == Some(ValueSource::CommandLine);
+		if self.algorithm.is_empty() {
+			self.algorithm = file_cnf.algorithm.unwrap_or_default();
+		}
+		if !from_cli("auid") {
+			if let Some(auid) = file_cnf.auid {
+				self.auid = auid;
+			}
+		}
+		if !from_cli("body_length") {
+			if let Some(body_length) = file_cnf.body_length {
+				self.body_length = body_length;
+			}
+		}
+		self.key_data_base = self.key_data_base.take().or(file_cnf.key_data_base);
+		if !from_cli("canonicalization") {
+			if let Some(canonicalization) = file_cnf.canonicalization {
+				self.canonicalization = canonicalization;
+			}
+		}
+		if !from_cli("copy_headers") {
+			if let Some(copy_headers) = file_cnf.copy_headers {
+				self.copy_headers = copy_headers;
+			}
+		}
+		self.domain_file = self.domain_file.take().or(file_cnf.domain_file);
+		self.revocation_list = self.revocation_list.take().or(file_cnf.revocation_list);
+		if self.header.is_empty() {
+			self.header = file_cnf.header.into_iter().collect();
+		}
+		if self.header_optional.is_empty() {
+			self.header_optional = file_cnf.header_optional.into_iter().collect();
+		}
+		if self.header_oversign.is_empty() {
+			self.header_oversign = file_cnf.header_oversign.into_iter().collect();
+		}
+		if !from_cli("cryptoperiod") {
+			if let Some(cryptoperiod) = file_cnf.cryptoperiod {
+				self.cryptoperiod = cryptoperiod;
+			}
+		}
+		if !from_cli("revocation") {
+			if let Some(revocation) = file_cnf.revocation {
+				self.revocation = revocation;
+			}
+		}
+		if !from_cli("publish_delay") {
+			if let Some(publish_delay) = file_cnf.publish_delay {
+				self.publish_delay = publish_delay;
+			}
+		}
+		self.dns_update_cmd = self.dns_update_cmd.take().or(file_cnf.dns_update_cmd);
+		self.txt_record_tags.merge(file_cnf.dns_record);
+		self.rfc2136_server = self.rfc2136_server.take().or(file_cnf.rfc2136_server);
+		self.rfc2136_tsig_name = self.rfc2136_tsig_name.take().or(file_cnf.rfc2136_tsig_name);
+		self.rfc2136_tsig_secret_file = self
+			.rfc2136_tsig_secret_file
+			.take()
+			.or(file_cnf.rfc2136_tsig_secret_file);
+		self.zone_file_dir = self.zone_file_dir.take().or(file_cnf.zone_file_dir);
+		self.zone_file_reload_cmd = self
+			.zone_file_reload_cmd
+			.take()
+			.or(file_cnf.zone_file_reload_cmd);
+		if !from_cli("expiration") {
+			if let Some(expiration) = file_cnf.expiration {
+				self.expiration = expiration;
+			}
+		}
+		if !from_cli("folding_width") {
+			if let Some(folding_width) = file_cnf.folding_width {
+				self.folding_width = folding_width;
+			}
+		}
+		if !from_cli("sign_policy") {
+			if let Some(sign_policy) = file_cnf.sign_policy {
+				self.sign_policy = sign_policy;
+			}
+		}
+		if self.local_network.is_empty() {
+			self.local_network = file_cnf.local_network.unwrap_or_default();
+		}
+		if self.sdid_source.is_empty() {
+			self.sdid_source = file_cnf.sdid_source.unwrap_or_default();
+		}
+		if !from_cli("spool_threshold") {
+			if let Some(spool_threshold) = file_cnf.spool_threshold {
+				self.spool_threshold = spool_threshold;
+			}
+		}
+		if !from_cli("subdomains") {
+			if let Some(subdomains) = file_cnf.subdomains {
+				self.subdomains = subdomains;
+			}
+		}
+		if self.own_subdomain.is_empty() {
+			self.own_subdomain = file_cnf.own_subdomain.unwrap_or_default();
+		}
+		file_cnf.domain
+	}
+
+	pub fn command(&self) -> Option<&Command> {
+		self.command.as_ref()
+	}
+
+	pub fn check_config(&self) -> bool {
+		self.check_config
+	}
+
+	/// Returns the warnings about the options which weaken the signatures. They are logged once,
+	/// when the configuration is loaded.
+	pub fn warnings(&self) -> Vec<String> {
+		let mut ret = Vec::new();
+		let mut domains = self.domain.clone();
+		domains.sort();
+		for domain in &domains {
+			for algorithm in self.algorithms(domain) {
+				if algorithm.is_weak() {
+					ret.push(format!("{domain}: {algorithm} is considered insecure and is only meant for receivers which do not support anything else"));
+				}
+			}
+			if self.body_length(domain) {
+				ret.push(format!("{domain}: the signatures include the body length, content appended to the messages will not invalidate them"));
+			}
+		}
+		ret
+	}
+
+	pub fn algorithms(&self, domain: &str) -> &[Algorithm] {
+		self.domain_config(domain)
+			.and_then(|d| d.algorithms())
+			.unwrap_or(&self.algorithm)
+	}
+
+	pub fn auid(&self, domain: &str) -> &AuidPolicy {
+		self.domain_config(domain)
+			.and_then(|d| d.auid())
+			.unwrap_or(&self.auid)
+	}
+
+	pub fn body_length(&self, domain: &str) -> bool {
+		self.domain_config(domain)
+			.and_then(|d| d.body_length())
+			.unwrap_or(self.body_length)
 	}
 
 	pub fn key_data_base(&self) -> PathBuf {
 		self.key_data_base.clone().unwrap()
 	}
 
-	pub fn canonicalization(&self) -> Canonicalization {
-		self.canonicalization
+	pub fn canonicalization(&self, domain: &str) -> Canonicalization {
+		self.domain_config(domain)
+			.and_then(|d| d.canonicalization())
+			.unwrap_or(self.canonicalization)
+	}
+
+	pub fn copy_headers(&self, domain: &str) -> bool {
+		self.domain_config(domain)
+			.and_then(|d| d.copy_headers())
+			.unwrap_or(self.copy_headers)
 	}
 
 	pub fn domains(&self) -> &[String] {
 		&self.domain
 	}
 
+	/// Returns the subdomains which are signed with their own keys.
+	pub fn own_subdomains(&self) -> &[String] {
+		&self.own_subdomain
+	}
+
+	/// Returns the nearest configured domain of which the given domain is a subdomain.
+	pub fn parent_domain<'a>(&self, domain: &'a str) -> Option<&'a str> {
+		domain
+			.match_indices('.')
+			.map(|(i, _)| &domain[i + 1..])
+			.find(|parent| self.domain.iter().any(|d| d == parent))
+	}
+
+	/// Returns the SDID to use for the given domain, if any, according to the subdomain policy of
+	/// the nearest configured domain.
+	pub fn signing_domain(&self, domain: &str) -> Option<String> {
+		if self.domain.iter().any(|d| d == domain) {
+			return Some(domain.to_string());
+		}
+		let parent = self.parent_domain(domain)?;
+		match self.subdomains(parent) {
+			SubdomainPolicy::None => None,
+			SubdomainPolicy::Parent => Some(parent.to_string()),
+			SubdomainPolicy::Own if self.own_subdomain.iter().any(|d| d == domain) => {
+				Some(domain.to_string())
+			}
+			SubdomainPolicy::Own => Some(parent.to_string()),
+		}
+	}
+
+	pub fn subdomains(&self, domain: &str) -> SubdomainPolicy {
+		self.domain_config(domain)
+			.and_then(|d| d.subdomains())
+			.unwrap_or(self.subdomains)
+	}
+
+	// Subdomains signed with their own keys inherit the parameters of their parent domain.
+	fn domain_config(&self, domain: &str) -> Option<&DomainConfig> {
+		self.domain_config.get(domain).or_else(|| {
+			if self.domain.iter().any(|d| d == domain) {
+				return None;
+			}
+			self.parent_domain(domain)
+				.and_then(|parent| self.domain_config.get(parent))
+		})
+	}
+
 	pub fn revocation_list(&self) -> Option<&Path> {
 		match &self.revocation_list {
 			Some(p) => Some(p),
@@ -72,33 +424,99 @@ impl Config {
 		}
 	}
 
-	pub fn headers(&self) -> &[String] {
-		&self.header
+	pub fn headers(&self, domain: &str) -> &[String] {
+		self.domain_config(domain)
+			.and_then(|d| d.headers())
+			.unwrap_or(&self.header)
+	}
+
+	pub fn headers_optional(&self, domain: &str) -> &[String] {
+		self.domain_config(domain)
+			.and_then(|d| d.headers_optional())
+			.unwrap_or(&self.header_optional)
+	}
+
+	pub fn headers_oversign(&self, domain: &str) -> &[String] {
+		self.domain_config(domain)
+			.and_then(|d| d.headers_oversign())
+			.unwrap_or(&self.header_oversign)
+	}
+
+	pub fn cryptoperiod(&self, domain: &str) -> NonZeroU64 {
+		self.domain_config(domain)
+			.and_then(|d| d.cryptoperiod())
+			.unwrap_or(self.cryptoperiod)
+	}
+
+	pub fn revocation(&self, domain: &str) -> u64 {
+		self.domain_config(domain)
+			.and_then(|d| d.revocation())
+			.unwrap_or(self.revocation)
+	}
+
+	pub fn publish_delay(&self, domain: &str) -> u64 {
+		self.domain_config(domain)
+			.and_then(|d| d.publish_delay())
+			.unwrap_or(self.publish_delay)
+	}
+
+	pub fn has_dns_update_cmd(&self) -> bool {
+		self.dns_update_cmd.is_some()
+	}
+
+	pub fn dns_update_cmd(&self) -> &DnsUpdateCmd {
+		self.dns_update_cmd.as_ref().unwrap_or(&DnsUpdateCmd::None)
+	}
+
+	pub fn txt_record_tags(&self) -> &TxtRecordTags {
+		&self.txt_record_tags
+	}
+
+	pub fn rfc2136(&self) -> Result<&Rfc2136Config> {
+		self.rfc2136
+			.as_ref()
+			.ok_or(anyhow!("the RFC 2136 DNS update is not configured"))
+	}
+
+	pub fn zone_file_dir(&self) -> Option<&Path> {
+		self.zone_file_dir.as_deref()
+	}
+
+	pub fn zone_file_reload_cmd(&self) -> Option<&str> {
+		self.zone_file_reload_cmd.as_deref()
 	}
 
-	pub fn headers_optional(&self) -> &[String] {
-		&self.header_optional
+	pub fn folding_width(&self) -> usize {
+		self.folding_width
 	}
 
-	pub fn cryptoperiod(&self) -> NonZeroU64 {
-		self.cryptoperiod
+	pub fn sign_policy(&self) -> SignPolicy {
+		self.sign_policy
 	}
 
-	pub fn revocation(&self) -> u64 {
-		self.revocation
+	pub fn local_networks(&self) -> &[IpNetwork] {
+		&self.local_network
 	}
 
-	pub fn dns_update_cmd(&self) -> &str {
-		&self.dns_update_cmd
+	pub fn sdid_sources(&self) -> &[SdidSource] {
+		&self.sdid_source
+	}
+
+	pub fn spool_threshold(&self) -> usize {
+		self.spool_threshold
 	}
 
 	pub fn verbosity(&self) -> log::LevelFilter {
 		crate::logs::log_level(self.verbose)
 	}
 
-	pub fn expiration(&self) -> Option<u64> {
-		if self.expiration != 0 {
-			Some(self.expiration)
+	pub fn expiration(&self, domain: &str) -> Option<u64> {
+		let expiration = self
+			.domain_config(domain)
+			.and_then(|d| d.expiration())
+			.unwrap_or(self.expiration);
+		if expiration != 0 {
+			Some(expiration)
 		} else {
 			None
 		}
@@ -117,19 +535,86 @@ fn process_key_data_base(opt: Option<PathBuf>) -> Option<PathBuf> {
 	}
 }
 
-fn process_domains(lst: &[String], domain_file: &Option<PathBuf>) -> Result<Vec<String>> {
-	let mut domain_set: HashSet<String> = lst.iter().map(|e| e.to_string()).collect();
+fn process_algorithms(lst: &[Algorithm]) -> Vec<Algorithm> {
+	if lst.is_empty() {
+		return vec![Algorithm::default()];
+	}
+	let mut ret: Vec<Algorithm> = Vec::with_capacity(lst.len());
+	for algorithm in lst {
+		if !ret.contains(algorithm) {
+			ret.push(*algorithm);
+		}
+	}
+	ret
+}
+
+fn process_sdid_sources(lst: &[SdidSource]) -> Vec<SdidSource> {
+	if lst.is_empty() {
+		return vec![SdidSource::default()];
+	}
+	let mut ret: Vec<SdidSource> = Vec::with_capacity(lst.len());
+	for source in lst {
+		if !ret.contains(source) {
+			ret.push(*source);
+		}
+	}
+	ret
+}
+
+fn process_domains(
+	lst: &[String],
+	domain_file: &Option<PathBuf>,
+	file_domains: HashMap<String, DomainConfig>,
+) -> Result<(Vec<String>, HashMap<String, DomainConfig>)> {
+	let mut domain_set: HashSet<String> = lst.iter().map(|e| e.to_lowercase()).collect();
+	let mut domain_config = HashMap::with_capacity(file_domains.len());
+	for (domain, cnf) in file_domains {
+		let domain = domain.to_lowercase();
+		domain_set.insert(domain.clone());
+		domain_config.insert(domain, cnf);
+	}
 	if let Some(path) = domain_file {
 		let f = File::open(path).map_err(|e| anyhow!("{}: {e}", path.display()))?;
-		for line in BufReader::new(f).lines() {
+		for (i, line) in BufReader::new(f).lines().enumerate() {
 			let line = line.map_err(|e| anyhow!("{}: {e}", path.display()))?;
-			let domain = line.trim();
-			if !domain.is_empty() && !domain.starts_with('#') {
-				domain_set.insert(domain.to_string().to_lowercase());
+			let line = line.trim();
+			if !line.is_empty() && !line.starts_with('#') {
+				let (domain, cnf) = crate::domain_config::parse_line(line)
+					.map_err(|e| anyhow!("{}:{}: {e}", path.display(), i + 1))?;
+				if domain_config.contains_key(&domain) {
+					return Err(anyhow!(
+						"{}:{}: {domain}: domain already defined in the configuration file",
+						path.display(),
+						i + 1
+					));
+				}
+				domain_set.insert(domain.clone());
+				domain_config.insert(domain, cnf);
 			}
 		}
 	}
-	Ok(domain_set.into_iter().collect::<Vec<_>>())
+	Ok((domain_set.into_iter().collect::<Vec<_>>(), domain_config))
+}
+
+fn process_rfc2136(
+	server: &Option<String>,
+	tsig_name: &Option<String>,
+	tsig_secret_file: &Option<PathBuf>,
+) -> Result<Rfc2136Config> {
+	let server = server
+		.as_ref()
+		.ok_or(anyhow!("builtin:rfc2136 requires --rfc2136-server"))?;
+	let tsig_name = tsig_name
+		.as_ref()
+		.ok_or(anyhow!("builtin:rfc2136 requires --rfc2136-tsig-name"))?;
+	let path = tsig_secret_file.as_ref().ok_or(anyhow!(
+		"builtin:rfc2136 requires --rfc2136-tsig-secret-file"
+	))?;
+	let secret = std::fs::read_to_string(path).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+	let secret = general_purpose::STANDARD
+		.decode(secret.trim())
+		.map_err(|e| anyhow!("{}: {e}", path.display()))?;
+	Ok(Rfc2136Config::new(server, tsig_name, secret))
 }
 
 fn process_headers(lst: &[String], default: &str) -> Vec<String> {
@@ -145,9 +630,146 @@ fn process_headers(lst: &[String], default: &str) -> Vec<String> {
 fn do_process_headers(lst: &[String]) -> HashSet<String> {
 	let mut ret = HashSet::with_capacity(128);
 	for input in lst {
-		for h in input.split(':') {
+		for h in input.split(':').map(|h| h.trim()).filter(|h| !h.is_empty()) {
 			ret.insert(h.to_string().to_lowercase());
 		}
 	}
 	ret
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn config(args: &[&str]) -> Config {
+		Config::from_args(args)
+	}
+
+	#[test]
+	fn subdomains_none() {
+		let cnf = config(&["-d", "example.org"]);
+		assert_eq!(cnf.signing_domain("example.org").unwrap(), "example.org");
+		assert!(cnf.signing_domain("news.example.org").is_none());
+		assert!(cnf.signing_domain("example.com").is_none());
+	}
+
+	#[test]
+	fn subdomains_parent() {
+		let cnf = config(&["--subdomains", "parent", "-d", "example.org"]);
+		assert_eq!(
+			cnf.signing_domain("news.example.org").unwrap(),
+			"example.org"
+		);
+		assert_eq!(
+			cnf.signing_domain("a.news.example.org").unwrap(),
+			"example.org"
+		);
+		assert!(cnf.signing_domain("badexample.org").is_none());
+	}
+
+	#[test]
+	fn subdomains_own() {
+		let cnf = config(&[
+			"--subdomains",
+			"own",
+			"-d",
+			"example.org",
+			"-d",
+			"lists.example.org",
+			"--own-subdomain",
+			"News.example.org",
+		]);
+		assert_eq!(cnf.own_subdomains(), &["news.example.org"]);
+		assert_eq!(
+			cnf.signing_domain("news.example.org").unwrap(),
+			"news.example.org"
+		);
+		assert_eq!(
+			cnf.signing_domain("billing.example.org").unwrap(),
+			"example.org"
+		);
+		assert_eq!(
+			cnf.signing_domain("a.news.example.org").unwrap(),
+			"example.org"
+		);
+		assert_eq!(
+			cnf.parent_domain("a.lists.example.org"),
+			Some("lists.example.org")
+		);
+		assert_eq!(cnf.parent_domain("lists.example.org"), Some("example.org"));
+		assert_eq!(cnf.parent_domain("example.org"), None);
+	}
+
+	#[test]
+	fn own_subdomains_invalid() {
+		let parse = |args: &[&str]| {
+			let mut cnf = Config::try_parse_from([&["filter-dkimout"], args].concat()).unwrap();
+			(cnf.domain, cnf.domain_config) =
+				process_domains(&cnf.domain, &None, HashMap::new()).unwrap();
+			cnf.process_own_subdomains()
+		};
+		assert!(parse(&["-d", "example.org", "--own-subdomain", "news.example.org"]).is_err());
+		assert!(parse(&[
+			"--subdomains",
+			"own",
+			"-d",
+			"example.org",
+			"--own-subdomain",
+			"news.example.com"
+		])
+		.is_err());
+		assert!(parse(&[
+			"--subdomains",
+			"own",
+			"-d",
+			"example.org",
+			"-d",
+			"news.example.org",
+			"--own-subdomain",
+			"news.example.org"
+		])
+		.is_err());
+	}
+
+	#[test]
+	fn warnings() {
+		assert!(config(&["-d", "example.org"]).warnings().is_empty());
+		let warnings =
+			config(&["--body-length", "-d", "example.org", "-d", "example.com"]).warnings();
+		assert_eq!(warnings.len(), 2);
+		assert!(warnings[0].starts_with("example.com: "));
+		let warnings = config(&[
+			"-a",
+			"rsa2048-sha1",
+			"-a",
+			"ed25519-sha256",
+			"-d",
+			"example.org",
+		])
+		.warnings();
+		assert_eq!(warnings.len(), 1);
+		assert!(warnings[0].starts_with("example.org: rsa2048-sha1 is considered insecure"));
+	}
+
+	#[test]
+	fn merge_bool_flags() {
+		let file_cnf: FileConfig = toml::from_str(
+			"body-length = true\ncopy-headers = false\n[dns-record]\nstrict = true\ntesting = true\n",
+		)
+		.unwrap();
+		let matches = <Config as CommandFactory>::command()
+			.try_get_matches_from([
+				"filter-dkimout",
+				"--body-length=false",
+				"--copy-headers",
+				"--dns-strict=false",
+			])
+			.unwrap();
+		let mut cnf = Config::from_arg_matches(&matches).unwrap();
+		cnf.merge_file_config(&matches, file_cnf);
+		assert!(!cnf.body_length("example.org"));
+		assert!(cnf.copy_headers("example.org"));
+		let txt = crate::dns::txt_record(cnf.txt_record_tags(), Algorithm::Ed25519Sha256, "test");
+		assert_eq!(txt, "v=DKIM1; k=ed25519; t=y; p=test");
+	}
+}
@@ -0,0 +1,156 @@
+use crate::algorithm::Algorithm;
+use crate::canonicalization::Canonicalization;
+use crate::dns::{DnsUpdateCmd, TxtRecordTags};
+use crate::domain_config::DomainConfig;
+use crate::sdid::{AuidPolicy, SdidSource, SubdomainPolicy};
+use crate::session::{IpNetwork, SignPolicy};
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::num::NonZeroU64;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct FileConfig {
+	pub algorithm: Option<Vec<Algorithm>>,
+	pub auid: Option<AuidPolicy>,
+	pub body_length: Option<bool>,
+	pub key_data_base: Option<PathBuf>,
+	pub canonicalization: Option<Canonicalization>,
+	pub copy_headers: Option<bool>,
+	pub domain: HashMap<String, DomainConfig>,
+	pub domain_file: Option<PathBuf>,
+	pub revocation_list: Option<PathBuf>,
+	pub header: Option<String>,
+	pub header_optional: Option<String>,
+	pub header_oversign: Option<String>,
+	pub cryptoperiod: Option<NonZeroU64>,
+	pub revocation: Option<u64>,
+	pub publish_delay: Option<u64>,
+	pub dns_update_cmd: Option<DnsUpdateCmd>,
+	pub dns_record: TxtRecordTags,
+	pub rfc2136_server: Option<String>,
+	pub rfc2136_tsig_name: Option<String>,
+	pub rfc2136_tsig_secret_file: Option<PathBuf>,
+	pub zone_file_dir: Option<PathBuf>,
+	pub zone_file_reload_cmd: Option<String>,
+	pub expiration: Option<u64>,
+	pub folding_width: Option<usize>,
+	pub sign_policy: Option<SignPolicy>,
+	pub local_network: Option<Vec<IpNetwork>>,
+	pub sdid_source: Option<Vec<SdidSource>>,
+	pub spool_threshold: Option<usize>,
+	pub subdomains: Option<SubdomainPolicy>,
+	pub own_subdomain: Option<Vec<String>>,
+}
+
+impl FileConfig {
+	pub fn load(path: &Path) -> Result<Self> {
+		let content =
+			std::fs::read_to_string(path).map_err(|e| anyhow!("{}: {e}", path.display()))?;
+		Self::parse(&content).map_err(|e| anyhow!("{}: {e}", path.display()))
+	}
+
+	fn parse(content: &str) -> Result<Self> {
+		Ok(toml::from_str(content)?)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn empty() {
+		let cnf = FileConfig::parse("").unwrap();
+		assert!(cnf.algorithm.is_none());
+		assert!(cnf.domain.is_empty());
+		assert!(cnf.dns_update_cmd.is_none());
+	}
+
+	#[test]
+	fn full() {
+		let content = r#"
+algorithm = ["ed25519-sha256", "rsa2048-sha256"]
+key-data-base = "/var/lib/filter-dkimout/key-db.sqlite3"
+canonicalization = "relaxed/simple"
+header = "from:to:subject"
+header-oversign = "from:subject"
+cryptoperiod = 31536000
+dns-update-cmd = "builtin:zonefile"
+zone-file-dir = "/var/nsd/zones/dkim"
+sign-policy = "auth-or-local"
+auid = "from-address"
+local-network = ["192.0.2.0/24", "2001:db8::/32"]
+sdid-source = ["envelope", "from"]
+subdomains = "parent"
+own-subdomain = ["news.example.com"]
+
+[dns-record]
+service-email = true
+notes = "Contact: postmaster@example.org"
+
+[domain."example.org"]
+
+[domain."Example.com"]
+algorithm = ["rsa2048-sha256"]
+body-length = true
+canonicalization = "simple/simple"
+copy-headers = true
+header-optional = ""
+subdomains = "own"
+"#;
+		let cnf = FileConfig::parse(content).unwrap();
+		assert_eq!(
+			cnf.algorithm.unwrap(),
+			vec![Algorithm::Ed25519Sha256, Algorithm::Rsa2048Sha256]
+		);
+		assert_eq!(cnf.canonicalization.unwrap().to_string(), "relaxed/simple");
+		assert_eq!(cnf.cryptoperiod.unwrap().get(), 31536000);
+		assert!(matches!(cnf.dns_update_cmd, Some(DnsUpdateCmd::ZoneFile)));
+		assert_eq!(cnf.sign_policy, Some(SignPolicy::AuthOrLocal));
+		assert_eq!(cnf.auid, Some(AuidPolicy::FromAddress));
+		assert_eq!(cnf.local_network.unwrap().len(), 2);
+		assert_eq!(
+			cnf.sdid_source.unwrap(),
+			vec![SdidSource::Envelope, SdidSource::From]
+		);
+		assert_eq!(cnf.subdomains, Some(SubdomainPolicy::Parent));
+		assert_eq!(cnf.own_subdomain.unwrap(), vec!["news.example.com"]);
+		assert_eq!(cnf.domain.len(), 2);
+		let domain = cnf.domain.get("Example.com").unwrap();
+		assert_eq!(domain.algorithms().unwrap(), &[Algorithm::Rsa2048Sha256]);
+		assert_eq!(domain.body_length(), Some(true));
+		assert_eq!(domain.copy_headers(), Some(true));
+		assert!(domain.headers().is_none());
+		assert!(domain.headers_optional().unwrap().is_empty());
+		assert_eq!(domain.subdomains(), Some(SubdomainPolicy::Own));
+		assert!(cnf
+			.domain
+			.get("example.org")
+			.unwrap()
+			.algorithms()
+			.is_none());
+	}
+
+	#[test]
+	fn error_line() {
+		let content =
+			"cryptoperiod = 42\n\n[domain.\"example.org\"]\nalgorithm = [\"rsa1234-sha256\"]\n";
+		let err = FileConfig::parse(content).unwrap_err().to_string();
+		assert!(err.contains("line 4"), "{err}");
+		assert!(
+			err.contains("rsa1234-sha256: invalid signing algorithm"),
+			"{err}"
+		);
+		let err = FileConfig::parse("unknown = true\n")
+			.unwrap_err()
+			.to_string();
+		assert!(err.contains("line 1"), "{err}");
+		let err = FileConfig::parse("cryptoperiod = 0\n")
+			.unwrap_err()
+			.to_string();
+		assert!(err.contains("line 1"), "{err}");
+	}
+}
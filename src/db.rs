@@ -12,7 +12,9 @@ pub const INSERT_KEY: &str = "INSERT INTO key_db (
 	revocation,
 	published,
 	private_key,
-	public_key
+	public_key,
+	dns_updated,
+	usable_after
 ) VALUES (
 	$1,
 	$2,
@@ -22,14 +24,100 @@ pub const INSERT_KEY: &str = "INSERT INTO key_db (
 	$6,
 	FALSE,
 	$7,
-	$8
+	$8,
+	FALSE,
+	$9
+)";
+pub const INSERT_IMPORTED_KEY: &str = "INSERT INTO key_db (
+	selector,
+	sdid,
+	algorithm,
+	creation,
+	not_after,
+	revocation,
+	published,
+	private_key,
+	public_key,
+	dns_updated,
+	usable_after,
+	imported
+) VALUES (
+	$1,
+	$2,
+	$3,
+	$4,
+	$5,
+	$6,
+	FALSE,
+	$7,
+	$8,
+	TRUE,
+	$4,
+	TRUE
 )";
 pub const SELECT_EXPIRED_KEYS: &str = "SELECT selector, sdid, algorithm, private_key
 FROM key_db
 WHERE
 	revocation <= unixepoch()
 	AND published IS FALSE
+	AND dns_deleted IS TRUE
+ORDER BY revocation";
+pub const SELECT_EXPIRED_RECORDS: &str = "SELECT selector, sdid, algorithm, public_key
+FROM key_db
+WHERE
+	revocation <= unixepoch()
+	AND dns_deleted IS FALSE
 ORDER BY revocation";
+pub const SELECT_KEY: &str = "SELECT
+	selector,
+	sdid,
+	algorithm,
+	creation,
+	not_after,
+	revocation,
+	published,
+	dns_updated,
+	usable_after,
+	public_key,
+	imported
+FROM key_db
+WHERE
+	selector = $1
+	AND ($2 IS NULL OR sdid = $2)
+ORDER BY sdid";
+pub const SELECT_ACTIVE_KEYS: &str = "SELECT selector, sdid, algorithm, public_key
+FROM key_db
+WHERE
+	($1 IS NULL OR sdid = $1)
+	AND revocation > unixepoch()
+	AND published IS FALSE
+ORDER BY sdid, creation";
+pub const SELECT_EXPORT_KEYS: &str = "SELECT selector, sdid, algorithm, private_key, public_key
+FROM key_db
+WHERE
+	($1 IS NULL OR selector = $1)
+	AND ($2 IS NULL OR sdid = $2)
+ORDER BY sdid, creation";
+pub const SELECT_KEY_EXISTS: &str = "SELECT selector
+FROM key_db
+WHERE
+	selector = $1
+	AND sdid = $2";
+pub const SELECT_KEYS: &str = "SELECT
+	selector,
+	sdid,
+	algorithm,
+	creation,
+	not_after,
+	revocation,
+	published,
+	dns_updated,
+	usable_after,
+	public_key,
+	imported
+FROM key_db
+WHERE $1 IS NULL OR sdid = $1
+ORDER BY sdid, creation";
 pub const SELECT_LATEST_KEY: &str = "SELECT not_after
 FROM key_db
 WHERE
@@ -38,19 +126,69 @@ WHERE
 	AND published IS FALSE
 ORDER BY not_after DESC
 LIMIT 1";
-pub const SELECT_LATEST_SIGNING_KEY: &str = "SELECT selector, private_key
+pub const SELECT_LATEST_SIGNING_KEY: &str = "SELECT selector, private_key, not_after
 FROM key_db
 WHERE
 	sdid = $1
 	AND algorithm = $2
 	AND published IS FALSE
+	AND dns_updated IS TRUE
+	AND usable_after <= unixepoch()
+	AND revocation > unixepoch()
 ORDER BY not_after DESC
 LIMIT 1";
+pub const SELECT_PENDING_SIGNING_KEY: &str = "SELECT dns_updated, usable_after
+FROM key_db
+WHERE
+	sdid = $1
+	AND algorithm = $2
+	AND published IS FALSE
+	AND revocation > unixepoch()
+	AND (dns_updated IS FALSE OR usable_after > unixepoch())
+ORDER BY dns_updated DESC, usable_after
+LIMIT 1";
+pub const SELECT_PENDING_DNS_UPDATES: &str = "SELECT selector, sdid, algorithm, public_key
+FROM key_db
+WHERE
+	dns_updated IS FALSE
+	AND revocation > unixepoch()
+	AND published IS FALSE
+ORDER BY creation";
 pub const SELECT_NEAREST_KEY_PUBLICATION: &str = "SELECT revocation
 FROM key_db
 WHERE published IS FALSE
 ORDER BY revocation
 LIMIT 1";
+pub const SELECT_ZONE_KEYS: &str = "SELECT selector, algorithm, public_key
+FROM key_db
+WHERE
+	sdid = $1
+	AND revocation > unixepoch()
+	AND published IS FALSE
+ORDER BY creation";
+pub const BACKUP_DB: &str = "VACUUM INTO $1";
+pub const UPDATE_DNS_DELETED_KEY: &str = "UPDATE key_db
+SET dns_deleted = TRUE
+WHERE
+	selector = $1
+	AND sdid = $2
+	AND algorithm = $3";
+pub const UPDATE_DNS_UPDATED_KEY: &str = "UPDATE key_db
+SET
+	dns_updated = TRUE,
+	usable_after = $4
+WHERE
+	selector = $1
+	AND sdid = $2
+	AND algorithm = $3";
+pub const UPDATE_REVOKE_KEY: &str = "UPDATE key_db
+SET
+	not_after = min(not_after, unixepoch()),
+	revocation = unixepoch()
+WHERE
+	selector = $1
+	AND sdid = $2
+	AND published IS FALSE";
 pub const UPDATE_PUBLISHED_KEY: &str = "UPDATE key_db
 SET published = TRUE
 WHERE
@@ -67,3 +205,16 @@ pub async fn init(cnf: &Config) -> Result<SqlitePool> {
 	sqlx::migrate!().run(&db_pool).await?;
 	Ok(db_pool)
 }
+
+#[cfg(test)]
+pub async fn init_test_db() -> SqlitePool {
+	let db_pool = SqlitePoolOptions::new()
+		.max_connections(1)
+		.idle_timeout(None)
+		.max_lifetime(None)
+		.connect("sqlite::memory:")
+		.await
+		.unwrap();
+	sqlx::migrate!().run(&db_pool).await.unwrap();
+	db_pool
+}
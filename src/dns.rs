@@ -0,0 +1,321 @@
+use crate::algorithm::Algorithm;
+use crate::config::Config;
+use anyhow::{anyhow, Result};
+use clap::Args;
+use serde::{de, Deserialize, Deserializer};
+use sqlx::SqlitePool;
+use std::str::FromStr;
+use tokio::process::Command;
+
+pub const BUILTIN_PREFIX: &str = "builtin:";
+pub const ENV_ACTION: &str = "DKIMOUT_ACTION";
+pub const ENV_ALGORITHM: &str = "DKIMOUT_ALGORITHM";
+pub const ENV_KEY_TYPE: &str = "DKIMOUT_KEY_TYPE";
+pub const ENV_PUBLIC_KEY: &str = "DKIMOUT_PUBLIC_KEY";
+pub const ENV_RECORD_NAME: &str = "DKIMOUT_RECORD_NAME";
+pub const ENV_RECORD_VALUE: &str = "DKIMOUT_RECORD_VALUE";
+pub const ENV_SDID: &str = "DKIMOUT_SDID";
+pub const ENV_SELECTOR: &str = "DKIMOUT_SELECTOR";
+
+#[derive(Args, Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct TxtRecordTags {
+	/// Add the h= tag to the DNS records
+	#[arg(
+		long = "dns-hash-algorithms",
+		num_args = 0..=1,
+		require_equals = true,
+		default_missing_value = "true"
+	)]
+	hash_algorithms: Option<bool>,
+	/// Add the s=email tag to the DNS records
+	#[arg(
+		long = "dns-service-email",
+		num_args = 0..=1,
+		require_equals = true,
+		default_missing_value = "true"
+	)]
+	service_email: Option<bool>,
+	/// Add the t=y flag to the DNS records
+	#[arg(
+		long = "dns-testing",
+		num_args = 0..=1,
+		require_equals = true,
+		default_missing_value = "true"
+	)]
+	testing: Option<bool>,
+	/// Add the t=s flag to the DNS records
+	#[arg(
+		long = "dns-strict",
+		num_args = 0..=1,
+		require_equals = true,
+		default_missing_value = "true"
+	)]
+	strict: Option<bool>,
+	/// Add the n= tag to the DNS records
+	#[arg(long = "dns-notes", value_name = "STRING")]
+	notes: Option<String>,
+}
+
+impl TxtRecordTags {
+	pub fn merge(&mut self, other: TxtRecordTags) {
+		self.hash_algorithms = self.hash_algorithms.or(other.hash_algorithms);
+		self.service_email = self.service_email.or(other.service_email);
+		self.testing = self.testing.or(other.testing);
+		self.strict = self.strict.or(other.strict);
+		if self.notes.is_none() {
+			self.notes = other.notes;
+		}
+	}
+}
+
+#[derive(Clone, Debug)]
+pub enum DnsUpdateCmd {
+	None,
+	Rfc2136,
+	ZoneFile,
+	Command(String),
+}
+
+impl FromStr for DnsUpdateCmd {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.strip_prefix(BUILTIN_PREFIX) {
+			Some(name) => match name.to_lowercase().as_str() {
+				"none" => Ok(Self::None),
+				"rfc2136" => Ok(Self::Rfc2136),
+				"zonefile" => Ok(Self::ZoneFile),
+				_ => Err(format!("{s}: invalid builtin DNS update command")),
+			},
+			None => {
+				if s.trim().is_empty() {
+					return Err(String::from("the DNS update command cannot be empty"));
+				}
+				Ok(Self::Command(s.to_string()))
+			}
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for DnsUpdateCmd {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		String::deserialize(deserializer)?
+			.parse()
+			.map_err(de::Error::custom)
+	}
+}
+
+pub async fn add_record(
+	db: &SqlitePool,
+	cnf: &Config,
+	selector: &str,
+	sdid: &str,
+	algorithm: Algorithm,
+	public_key: &str,
+) -> Result<()> {
+	match cnf.dns_update_cmd() {
+		DnsUpdateCmd::None => Ok(()),
+		DnsUpdateCmd::Rfc2136 => {
+			let name = record_name(selector, sdid);
+			let txt = txt_record(cnf.txt_record_tags(), algorithm, public_key);
+			crate::rfc2136::add_record(cnf.rfc2136()?, &name, &txt).await
+		}
+		DnsUpdateCmd::ZoneFile => crate::zone_file::update(db, cnf, sdid).await,
+		DnsUpdateCmd::Command(cmd) => {
+			run_cmd(
+				cmd,
+				"add",
+				cnf.txt_record_tags(),
+				selector,
+				sdid,
+				algorithm,
+				public_key,
+			)
+			.await
+		}
+	}
+}
+
+pub async fn delete_record(
+	db: &SqlitePool,
+	cnf: &Config,
+	selector: &str,
+	sdid: &str,
+	algorithm: Algorithm,
+	public_key: &str,
+) -> Result<()> {
+	match cnf.dns_update_cmd() {
+		DnsUpdateCmd::None => Ok(()),
+		DnsUpdateCmd::Rfc2136 => {
+			let name = record_name(selector, sdid);
+			crate::rfc2136::delete_record(cnf.rfc2136()?, &name).await
+		}
+		DnsUpdateCmd::ZoneFile => crate::zone_file::update(db, cnf, sdid).await,
+		DnsUpdateCmd::Command(cmd) => {
+			run_cmd(
+				cmd,
+				"delete",
+				cnf.txt_record_tags(),
+				selector,
+				sdid,
+				algorithm,
+				public_key,
+			)
+			.await
+		}
+	}
+}
+
+pub fn record_name(selector: &str, sdid: &str) -> String {
+	format!("{selector}._domainkey.{sdid}")
+}
+
+pub fn txt_record(tags: &TxtRecordTags, algorithm: Algorithm, public_key: &str) -> String {
+	let mut record = format!("v=DKIM1; k={};", algorithm.key_type());
+	if tags.hash_algorithms.unwrap_or(false) {
+		record += &format!(" h={};", algorithm.hash_algorithm());
+	}
+	if tags.service_email.unwrap_or(false) {
+		record += " s=email;";
+	}
+	let mut flags = Vec::with_capacity(2);
+	if tags.testing.unwrap_or(false) {
+		flags.push("y");
+	}
+	if tags.strict.unwrap_or(false) {
+		flags.push("s");
+	}
+	if !flags.is_empty() {
+		record += &format!(" t={};", flags.join(":"));
+	}
+	if let Some(notes) = &tags.notes {
+		record += &format!(" n={};", crate::quoted_printable::encode(notes.as_bytes()));
+	}
+	record += &format!(" p={public_key}");
+	record
+}
+
+async fn run_cmd(
+	cmd: &str,
+	action: &str,
+	tags: &TxtRecordTags,
+	selector: &str,
+	sdid: &str,
+	algorithm: Algorithm,
+	public_key: &str,
+) -> Result<()> {
+	let record_name = record_name(selector, sdid);
+	let algorithm_name = algorithm.to_string();
+	let key_type = algorithm.key_type();
+	let record_value = txt_record(tags, algorithm, public_key);
+	let envs = [
+		(ENV_ACTION, action),
+		(ENV_ALGORITHM, algorithm_name.as_str()),
+		(ENV_KEY_TYPE, key_type.as_str()),
+		(ENV_PUBLIC_KEY, public_key),
+		(ENV_RECORD_NAME, record_name.as_str()),
+		(ENV_RECORD_VALUE, record_value.as_str()),
+		(ENV_SDID, sdid),
+		(ENV_SELECTOR, selector),
+	];
+	run_shell_cmd(cmd, &record_name, "DNS update command", &envs).await
+}
+
+pub async fn run_shell_cmd(
+	cmd: &str,
+	name: &str,
+	description: &str,
+	envs: &[(&str, &str)],
+) -> Result<()> {
+	log::debug!("{name}: running the {description}: {cmd}");
+	let output = Command::new("/bin/sh")
+		.arg("-c")
+		.arg(cmd)
+		.envs(envs.iter().copied())
+		.kill_on_drop(true)
+		.output()
+		.await
+		.map_err(|e| anyhow!("{name}: unable to run the {description}: {e}"))?;
+	let stderr = String::from_utf8_lossy(&output.stderr);
+	for line in stderr.lines().filter(|l| !l.trim().is_empty()) {
+		if output.status.success() {
+			log::debug!("{name}: {description}: {line}");
+		} else {
+			log::warn!("{name}: {description}: {line}");
+		}
+	}
+	if !output.status.success() {
+		return Err(anyhow!("{name}: {description} failed: {}", output.status));
+	}
+	log::info!("{name}: {description} succeeded: {}", output.status);
+	Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const PUBLIC_KEY: &str = "11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo=";
+
+	#[test]
+	fn txt_record_default() {
+		let tags = TxtRecordTags::default();
+		assert_eq!(
+			txt_record(&tags, Algorithm::Ed25519Sha256, PUBLIC_KEY),
+			"v=DKIM1; k=ed25519; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo="
+		);
+	}
+
+	#[test]
+	fn txt_record_all_tags() {
+		let tags = TxtRecordTags {
+			hash_algorithms: Some(true),
+			service_email: Some(true),
+			testing: Some(true),
+			strict: Some(true),
+			notes: Some(String::from("Contact: postmaster")),
+		};
+		assert_eq!(
+			txt_record(&tags, Algorithm::Rsa2048Sha256, PUBLIC_KEY),
+			"v=DKIM1; k=rsa; h=sha256; s=email; t=y:s; n=Contact:=20postmaster; p=11qYAYKxCrfVS/7TyWQHOg7hcvPapiMlrwIaaPcHURo="
+		);
+	}
+
+	#[test]
+	fn parse_builtin_none() {
+		let cmd: DnsUpdateCmd = "builtin:none".parse().unwrap();
+		assert!(matches!(cmd, DnsUpdateCmd::None));
+	}
+
+	#[test]
+	fn parse_builtin_rfc2136() {
+		let cmd: DnsUpdateCmd = "builtin:rfc2136".parse().unwrap();
+		assert!(matches!(cmd, DnsUpdateCmd::Rfc2136));
+	}
+
+	#[test]
+	fn parse_builtin_zonefile() {
+		let cmd: DnsUpdateCmd = "builtin:zonefile".parse().unwrap();
+		assert!(matches!(cmd, DnsUpdateCmd::ZoneFile));
+	}
+
+	#[test]
+	fn parse_builtin_invalid() {
+		assert!("builtin:invalid".parse::<DnsUpdateCmd>().is_err());
+	}
+
+	#[test]
+	fn parse_command() {
+		let cmd: DnsUpdateCmd = "/usr/local/bin/update-dns --add".parse().unwrap();
+		match cmd {
+			DnsUpdateCmd::Command(c) => assert_eq!(c, "/usr/local/bin/update-dns --add"),
+			_ => panic!("invalid command"),
+		}
+	}
+
+	#[test]
+	fn parse_empty_command() {
+		assert!("  ".parse::<DnsUpdateCmd>().is_err());
+	}
+}
@@ -0,0 +1,231 @@
+use crate::algorithm::Algorithm;
+use crate::canonicalization::Canonicalization;
+use crate::sdid::{AuidPolicy, SubdomainPolicy};
+use anyhow::{anyhow, Result};
+use serde::{Deserialize, Deserializer};
+use std::num::NonZeroU64;
+
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct DomainConfig {
+	algorithm: Vec<Algorithm>,
+	auid: Option<AuidPolicy>,
+	body_length: Option<bool>,
+	canonicalization: Option<Canonicalization>,
+	copy_headers: Option<bool>,
+	cryptoperiod: Option<NonZeroU64>,
+	expiration: Option<u64>,
+	#[serde(deserialize_with = "deserialize_headers")]
+	header: Option<Vec<String>>,
+	#[serde(deserialize_with = "deserialize_headers")]
+	header_optional: Option<Vec<String>>,
+	#[serde(deserialize_with = "deserialize_headers")]
+	header_oversign: Option<Vec<String>>,
+	publish_delay: Option<u64>,
+	revocation: Option<u64>,
+	subdomains: Option<SubdomainPolicy>,
+}
+
+impl DomainConfig {
+	pub fn algorithms(&self) -> Option<&[Algorithm]> {
+		if self.algorithm.is_empty() {
+			None
+		} else {
+			Some(&self.algorithm)
+		}
+	}
+
+	pub fn auid(&self) -> Option<&AuidPolicy> {
+		self.auid.as_ref()
+	}
+
+	pub fn body_length(&self) -> Option<bool> {
+		self.body_length
+	}
+
+	pub fn canonicalization(&self) -> Option<Canonicalization> {
+		self.canonicalization
+	}
+
+	pub fn copy_headers(&self) -> Option<bool> {
+		self.copy_headers
+	}
+
+	pub fn cryptoperiod(&self) -> Option<NonZeroU64> {
+		self.cryptoperiod
+	}
+
+	pub fn expiration(&self) -> Option<u64> {
+		self.expiration
+	}
+
+	pub fn headers(&self) -> Option<&[String]> {
+		self.header.as_deref()
+	}
+
+	pub fn headers_optional(&self) -> Option<&[String]> {
+		self.header_optional.as_deref()
+	}
+
+	pub fn headers_oversign(&self) -> Option<&[String]> {
+		self.header_oversign.as_deref()
+	}
+
+	pub fn publish_delay(&self) -> Option<u64> {
+		self.publish_delay
+	}
+
+	pub fn revocation(&self) -> Option<u64> {
+		self.revocation
+	}
+
+	pub fn subdomains(&self) -> Option<SubdomainPolicy> {
+		self.subdomains
+	}
+
+	pub fn set(&mut self, name: &str, value: &str) -> Result<()> {
+		match name {
+			"algorithm" => {
+				for s in value.split(',') {
+					let algorithm = s.parse::<Algorithm>().map_err(|e| anyhow!(e))?;
+					if !self.algorithm.contains(&algorithm) {
+						self.algorithm.push(algorithm);
+					}
+				}
+			}
+			"auid" => {
+				self.auid = Some(value.parse().map_err(|e: String| anyhow!(e))?);
+			}
+			"body-length" => {
+				self.body_length = Some(value.parse().map_err(|e| anyhow!("{value}: {e}"))?);
+			}
+			"canonicalization" => {
+				self.canonicalization = Some(value.parse().map_err(|e: String| anyhow!(e))?);
+			}
+			"copy-headers" => {
+				self.copy_headers = Some(value.parse().map_err(|e| anyhow!("{value}: {e}"))?);
+			}
+			"cryptoperiod" => {
+				self.cryptoperiod = Some(value.parse().map_err(|e| anyhow!("{value}: {e}"))?);
+			}
+			"expiration" => {
+				self.expiration = Some(value.parse().map_err(|e| anyhow!("{value}: {e}"))?);
+			}
+			"header" => {
+				add_headers(&mut self.header, value);
+			}
+			"header-optional" => {
+				add_headers(&mut self.header_optional, value);
+			}
+			"header-oversign" => {
+				add_headers(&mut self.header_oversign, value);
+			}
+			"publish-delay" => {
+				self.publish_delay = Some(value.parse().map_err(|e| anyhow!("{value}: {e}"))?);
+			}
+			"revocation" => {
+				self.revocation = Some(value.parse().map_err(|e| anyhow!("{value}: {e}"))?);
+			}
+			"subdomains" => {
+				self.subdomains = Some(value.parse().map_err(|e: String| anyhow!(e))?);
+			}
+			_ => return Err(anyhow!("{name}: invalid domain parameter")),
+		}
+		Ok(())
+	}
+}
+
+fn add_headers(lst: &mut Option<Vec<String>>, value: &str) {
+	let lst = lst.get_or_insert_with(Vec::new);
+	for h in value.split(':') {
+		let h = h.trim().to_lowercase();
+		if !h.is_empty() && !lst.contains(&h) {
+			lst.push(h);
+		}
+	}
+}
+
+fn deserialize_headers<'de, D: Deserializer<'de>>(
+	deserializer: D,
+) -> Result<Option<Vec<String>>, D::Error> {
+	let mut ret = None;
+	add_headers(&mut ret, &String::deserialize(deserializer)?);
+	Ok(ret)
+}
+
+pub fn parse_line(line: &str) -> Result<(String, DomainConfig)> {
+	let mut tokens = line.split_whitespace();
+	let domain = tokens
+		.next()
+		.ok_or(anyhow!("missing domain name"))?
+		.to_lowercase();
+	let mut cnf = DomainConfig::default();
+	for token in tokens {
+		let (name, value) = token
+			.split_once('=')
+			.ok_or(anyhow!("{token}: invalid domain parameter"))?;
+		cnf.set(&name.to_lowercase(), value)
+			.map_err(|e| anyhow!("{domain}: {e}"))?;
+	}
+	Ok((domain, cnf))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn domain_only() {
+		let (domain, cnf) = parse_line("Example.org").unwrap();
+		assert_eq!(domain, "example.org");
+		assert!(cnf.algorithms().is_none());
+		assert!(cnf.auid().is_none());
+		assert!(cnf.body_length().is_none());
+		assert!(cnf.canonicalization().is_none());
+		assert!(cnf.copy_headers().is_none());
+		assert!(cnf.cryptoperiod().is_none());
+		assert!(cnf.expiration().is_none());
+		assert!(cnf.headers().is_none());
+		assert!(cnf.headers_optional().is_none());
+		assert!(cnf.headers_oversign().is_none());
+		assert!(cnf.publish_delay().is_none());
+		assert!(cnf.revocation().is_none());
+		assert!(cnf.subdomains().is_none());
+	}
+
+	#[test]
+	fn overrides() {
+		let (domain, cnf) = parse_line("example.org  algorithm=ed25519-sha256,rsa2048-sha256 auid=@news.example.org body-length=true canonicalization=simple/simple copy-headers=false\tcryptoperiod=31536000 expiration=0 header=From:To header=subject header-optional= header-oversign=From:Subject publish-delay=60 revocation=42 subdomains=own").unwrap();
+		assert_eq!(domain, "example.org");
+		assert_eq!(
+			cnf.algorithms().unwrap(),
+			&[Algorithm::Ed25519Sha256, Algorithm::Rsa2048Sha256]
+		);
+		assert_eq!(
+			cnf.auid().unwrap(),
+			&AuidPolicy::Fixed("@news.example.org".into())
+		);
+		assert_eq!(cnf.body_length(), Some(true));
+		assert_eq!(cnf.canonicalization().unwrap().to_string(), "simple/simple");
+		assert_eq!(cnf.copy_headers(), Some(false));
+		assert_eq!(cnf.cryptoperiod().unwrap().get(), 31536000);
+		assert_eq!(cnf.expiration(), Some(0));
+		assert_eq!(cnf.headers().unwrap(), &["from", "to", "subject"]);
+		assert!(cnf.headers_optional().unwrap().is_empty());
+		assert_eq!(cnf.headers_oversign().unwrap(), &["from", "subject"]);
+		assert_eq!(cnf.publish_delay(), Some(60));
+		assert_eq!(cnf.revocation(), Some(42));
+		assert_eq!(cnf.subdomains(), Some(SubdomainPolicy::Own));
+	}
+
+	#[test]
+	fn invalid() {
+		assert!(parse_line("").is_err());
+		assert!(parse_line("example.org algorithm").is_err());
+		assert!(parse_line("example.org algorithm=invalid").is_err());
+		assert!(parse_line("example.org body-length=yes").is_err());
+		assert!(parse_line("example.org cryptoperiod=0").is_err());
+		assert!(parse_line("example.org subdomains=all").is_err());
+		assert!(parse_line("example.org unknown=value").is_err());
+	}
+}
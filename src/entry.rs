@@ -1,12 +1,28 @@
+use crate::report::{Report, REPORT_TAG};
 use crate::stdin_reader::StdinReader;
 use anyhow::{anyhow, Result};
+use nom::branch::alt;
 use nom::bytes::streaming::{tag, take_till, take_while1};
+use nom::combinator::value;
 use nom::IResult;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 
+#[derive(Debug)]
+pub enum InputLine {
+	Entry(Entry),
+	Report(Report),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Phase {
+	DataLine,
+	Commit,
+}
+
 #[derive(Debug)]
 pub struct Entry {
+	phase: Phase,
 	session_id: String,
 	token: String,
 	data: Vec<u8>,
@@ -29,6 +45,10 @@ impl Entry {
 		&self.data
 	}
 
+	pub fn is_commit(&self) -> bool {
+		self.phase == Phase::Commit
+	}
+
 	pub fn is_end_of_message(&self) -> bool {
 		self.data == vec![b'.']
 	}
@@ -39,13 +59,19 @@ impl Entry {
 	}
 }
 
-pub async fn read_entry(reader_lock: Arc<RwLock<StdinReader>>) -> Option<Result<Entry>> {
+pub async fn read_entry(reader_lock: Arc<RwLock<StdinReader>>) -> Option<Result<InputLine>> {
 	let mut reader = reader_lock.write().await;
 	log::trace!("reader lock on stdin locked");
 	let line_res = reader.read_line().await;
 	drop(reader);
 	log::trace!("reader lock on stdin released");
-	line_res.map(|line| Entry::from_bytes(&line))
+	line_res.map(|line| {
+		if line.starts_with(REPORT_TAG) {
+			Report::from_bytes(&line).map(InputLine::Report)
+		} else {
+			Entry::from_bytes(&line).map(InputLine::Entry)
+		}
+	})
 }
 
 fn is_eol(c: u8) -> bool {
@@ -83,17 +109,54 @@ fn parse_entry(input: &[u8]) -> IResult<&[u8], Entry> {
 	let (input, _) = parse_delimiter(input)?;
 	let (input, _subsystem) = tag("smtp-in")(input)?;
 	let (input, _) = parse_delimiter(input)?;
-	let (input, _phase) = tag("data-line")(input)?;
+	let (input, phase) = alt((
+		value(Phase::DataLine, tag("data-line")),
+		value(Phase::Commit, tag("commit")),
+	))(input)?;
 	let (input, _) = parse_delimiter(input)?;
 	let (input, session_id) = parse_string_parameter(input)?;
 	let (input, _) = parse_delimiter(input)?;
 	let (input, token) = parse_string_parameter(input)?;
-	let (input, _) = parse_delimiter(input)?;
-	let (input, data) = parse_data(input)?;
+	let (input, data) = match phase {
+		Phase::DataLine => {
+			let (input, _) = parse_delimiter(input)?;
+			parse_data(input)?
+		}
+		Phase::Commit => (input, Vec::new()),
+	};
 	let entry = Entry {
+		phase,
 		session_id,
 		token,
 		data,
 	};
 	Ok((input, entry))
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn data_line() {
+		let entry = Entry::from_bytes(
+			b"filter|0.7|1681595158.123|smtp-in|data-line|7a8b|c9d0|Subject: test\n",
+		)
+		.unwrap();
+		assert!(!entry.is_commit());
+		assert_eq!(entry.get_msg_id(), "7a8b.c9d0");
+		assert_eq!(entry.get_data(), b"Subject: test");
+		let entry = Entry::from_bytes(b"filter|0.7|1681595158.123|smtp-in|data-line|7a8b|c9d0|.\n")
+			.unwrap();
+		assert!(entry.is_end_of_message());
+	}
+
+	#[test]
+	fn commit() {
+		let entry =
+			Entry::from_bytes(b"filter|0.7|1681595158.123|smtp-in|commit|7a8b|c9d0\n").unwrap();
+		assert!(entry.is_commit());
+		assert!(!entry.is_end_of_message());
+		assert_eq!(entry.get_msg_id(), "7a8b.c9d0");
+	}
+}
@@ -26,7 +26,11 @@ pub async fn read_config(reader: &mut StdinReader) {
 
 pub fn register_filter() {
 	log::trace!("registering the filter");
+	for event in crate::report::REPORT_EVENTS {
+		println!("register|report|smtp-in|{event}");
+	}
 	println!("register|filter|smtp-in|data-line");
+	println!("register|filter|smtp-in|commit");
 	println!("register|ready");
 	log::trace!("filter registered");
 }
@@ -1,6 +1,7 @@
 use crate::config::Config;
+use crate::dns::DnsUpdateCmd;
 use crate::Algorithm;
-use anyhow::Result;
+use anyhow::{anyhow, Result};
 use sqlx::types::time::OffsetDateTime;
 use sqlx::SqlitePool;
 use std::path::Path;
@@ -10,16 +11,26 @@ use tokio::time::Duration;
 use uuid::Uuid;
 
 pub async fn key_rotation(db: &SqlitePool, cnf: &Config) -> Duration {
-	let mut durations = Vec::with_capacity(cnf.domains().len());
-	let expiration = cnf
-		.expiration()
-		.map(Duration::from_secs)
-		.unwrap_or_else(|| Duration::from_secs(cnf.cryptoperiod().get() / 10));
-	for domain in cnf.domains() {
-		if let Ok(d) = renew_key_if_expired(db, cnf, domain, cnf.algorithm(), expiration).await {
-			durations.push(d);
+	let domains: Vec<&String> = cnf.domains().iter().chain(cnf.own_subdomains()).collect();
+	let mut durations = Vec::with_capacity(domains.len());
+	if let Err(err) = retry_dns_updates(db, cnf).await {
+		log::error!("{err}");
+	}
+	for domain in domains {
+		for algorithm in cnf.algorithms(domain) {
+			if let Ok(d) = renew_key_if_expired(db, cnf, domain, *algorithm).await {
+				durations.push(d);
+			}
+		}
+		if let DnsUpdateCmd::ZoneFile = cnf.dns_update_cmd() {
+			if let Err(err) = crate::zone_file::update(db, cnf, domain).await {
+				log::error!("{domain}: {err}");
+			}
 		}
 	}
+	if let Err(err) = delete_expired_records(db, cnf).await {
+		log::error!("{err}");
+	}
 	if let Some(path) = cnf.revocation_list() {
 		match publish_expired_keys(db, path).await {
 			Ok(d) => durations.push(d),
@@ -31,7 +42,65 @@ pub async fn key_rotation(db: &SqlitePool, cnf: &Config) -> Duration {
 	durations[durations.len() - 1]
 }
 
-async fn publish_expired_keys(db: &SqlitePool, file_path: &Path) -> Result<Duration> {
+/// Returns the SDID to sign with using the given algorithm. Keys of subdomains which use their own
+/// keys are generated during the key rotation and, until they are usable, the parent domain is
+/// used instead.
+pub async fn usable_sdid(
+	db: &SqlitePool,
+	cnf: &Config,
+	sdid: &str,
+	algorithm: Algorithm,
+) -> String {
+	let parent = match cnf.parent_domain(sdid) {
+		Some(parent) if !cnf.domains().iter().any(|d| d == sdid) => parent,
+		_ => return sdid.to_string(),
+	};
+	let res: Result<Option<(String, String, i64)>, _> =
+		sqlx::query_as(crate::db::SELECT_LATEST_SIGNING_KEY)
+			.bind(sdid)
+			.bind(algorithm.to_string())
+			.fetch_optional(db)
+			.await;
+	match res {
+		Ok(Some(_)) => sdid.to_string(),
+		Ok(None) => {
+			log::info!("{sdid}: no usable {algorithm} key yet, using the {parent} key instead");
+			parent.to_string()
+		}
+		Err(err) => {
+			log::error!("{sdid}: {err}");
+			parent.to_string()
+		}
+	}
+}
+
+/// Deletes the DNS records of the revoked keys. Failed deletions are retried during the next key
+/// rotation check and the private keys are only published once their record has been deleted.
+pub async fn delete_expired_records(db: &SqlitePool, cnf: &Config) -> Result<()> {
+	let res: Vec<(String, String, String, String)> =
+		sqlx::query_as(crate::db::SELECT_EXPIRED_RECORDS)
+			.fetch_all(db)
+			.await?;
+	for (selector, sdid, algorithm, public_key) in res {
+		let algorithm = algorithm.parse::<Algorithm>().map_err(|e| anyhow!(e))?;
+		if let Err(err) =
+			crate::dns::delete_record(db, cnf, &selector, &sdid, algorithm, &public_key).await
+		{
+			log::error!("{err}");
+			continue;
+		}
+		sqlx::query(crate::db::UPDATE_DNS_DELETED_KEY)
+			.bind(&selector)
+			.bind(&sdid)
+			.bind(algorithm.to_string())
+			.execute(db)
+			.await?;
+		log::debug!("{selector}._domainkey.{sdid}: DNS record deleted");
+	}
+	Ok(())
+}
+
+pub async fn publish_expired_keys(db: &SqlitePool, file_path: &Path) -> Result<Duration> {
 	let res: Vec<(String, String, String, String)> = sqlx::query_as(crate::db::SELECT_EXPIRED_KEYS)
 		.fetch_all(db)
 		.await?;
@@ -44,8 +113,9 @@ async fn publish_expired_keys(db: &SqlitePool, file_path: &Path) -> Result<Durat
 			.await?;
 		let mut buff = BufWriter::new(rev_file);
 		for (selector, sdid, algorithm, private_key) in res {
-			let algorithm = algorithm.parse::<Algorithm>().unwrap().key_type();
-			buff.write_all(algorithm.as_bytes()).await?;
+			let algorithm = algorithm.parse::<Algorithm>().unwrap();
+			let key_type = algorithm.key_type();
+			buff.write_all(key_type.as_bytes()).await?;
 			buff.write_all(b" ").await?;
 			buff.write_all(private_key.as_bytes()).await?;
 			buff.write_all(b" ").await?;
@@ -57,11 +127,11 @@ async fn publish_expired_keys(db: &SqlitePool, file_path: &Path) -> Result<Durat
 			sqlx::query(crate::db::UPDATE_PUBLISHED_KEY)
 				.bind(&selector)
 				.bind(&sdid)
-				.bind(&algorithm)
+				.bind(algorithm.to_string())
 				.execute(db)
 				.await?;
 			log::info!(
-				"{algorithm} private key for {selector}._domainkey.{sdid} has been published"
+				"{key_type} private key for {selector}._domainkey.{sdid} has been published"
 			);
 		}
 	}
@@ -83,8 +153,11 @@ async fn renew_key_if_expired(
 	cnf: &Config,
 	domain: &str,
 	algorithm: Algorithm,
-	expiration: Duration,
 ) -> Result<Duration> {
+	let expiration = cnf
+		.expiration(domain)
+		.map(Duration::from_secs)
+		.unwrap_or_else(|| Duration::from_secs(cnf.cryptoperiod(domain).get() / 10));
 	let res: Option<(i64,)> = sqlx::query_as(crate::db::SELECT_LATEST_KEY)
 		.bind(domain)
 		.bind(algorithm.to_string())
@@ -93,20 +166,21 @@ async fn renew_key_if_expired(
 	match res {
 		Some((not_after,)) => {
 			let not_after = OffsetDateTime::from_unix_timestamp(not_after)?;
-			log::debug!("{domain}: key is valid until {not_after}");
-			if not_after - expiration <= OffsetDateTime::now_utc() {
+			log::debug!("{domain}: {algorithm} key is valid until {not_after}");
+			let publish_delay = Duration::from_secs(cnf.publish_delay(domain));
+			if not_after - expiration - publish_delay <= OffsetDateTime::now_utc() {
 				generate_key(db, cnf, domain, algorithm).await?;
 			}
 		}
 		None => {
-			log::debug!("no key found for domain {domain}");
+			log::debug!("no {algorithm} key found for domain {domain}");
 			generate_key(db, cnf, domain, algorithm).await?;
 		}
 	};
 	Ok(Duration::from_secs(10))
 }
 
-async fn generate_key(
+pub async fn generate_key(
 	db: &SqlitePool,
 	cnf: &Config,
 	domain: &str,
@@ -114,21 +188,64 @@ async fn generate_key(
 ) -> Result<()> {
 	let selector = format!("dkim-{}", Uuid::new_v4().simple());
 	let now = OffsetDateTime::now_utc();
-	let not_after = now + Duration::from_secs(cnf.cryptoperiod().get());
-	let revocation = not_after + Duration::from_secs(cnf.revocation());
+	let not_after = now + Duration::from_secs(cnf.cryptoperiod(domain).get());
+	let revocation = not_after + Duration::from_secs(cnf.revocation(domain));
+	let usable_after = now + Duration::from_secs(cnf.publish_delay(domain));
 	let (priv_key, pub_key) = algorithm.gen_keys();
 	sqlx::query(crate::db::INSERT_KEY)
-		.bind(selector)
+		.bind(&selector)
 		.bind(domain)
 		.bind(algorithm.to_string())
 		.bind(now.unix_timestamp())
 		.bind(not_after.unix_timestamp())
 		.bind(revocation.unix_timestamp())
 		.bind(priv_key)
-		.bind(pub_key)
+		.bind(&pub_key)
+		.bind(usable_after.unix_timestamp())
 		.execute(db)
 		.await?;
-	// TODO: dns_update_cmd
-	log::debug!("{domain}: new {} key generated", algorithm.to_string());
+	log::debug!("{domain}: new {algorithm} key generated");
+	update_dns(db, cnf, &selector, domain, algorithm, &pub_key).await;
 	Ok(())
 }
+
+async fn retry_dns_updates(db: &SqlitePool, cnf: &Config) -> Result<()> {
+	let res: Vec<(String, String, String, String)> =
+		sqlx::query_as(crate::db::SELECT_PENDING_DNS_UPDATES)
+			.fetch_all(db)
+			.await?;
+	for (selector, sdid, algorithm, public_key) in res {
+		let algorithm = algorithm.parse::<Algorithm>().map_err(|e| anyhow!(e))?;
+		log::debug!("{selector}._domainkey.{sdid}: retrying the DNS update");
+		update_dns(db, cnf, &selector, &sdid, algorithm, &public_key).await;
+	}
+	Ok(())
+}
+
+async fn update_dns(
+	db: &SqlitePool,
+	cnf: &Config,
+	selector: &str,
+	sdid: &str,
+	algorithm: Algorithm,
+	public_key: &str,
+) {
+	if let Err(err) = crate::dns::add_record(db, cnf, selector, sdid, algorithm, public_key).await {
+		log::error!("{err}");
+		return;
+	}
+	let usable_after = OffsetDateTime::now_utc() + Duration::from_secs(cnf.publish_delay(sdid));
+	let res = sqlx::query(crate::db::UPDATE_DNS_UPDATED_KEY)
+		.bind(selector)
+		.bind(sdid)
+		.bind(algorithm.to_string())
+		.bind(usable_after.unix_timestamp())
+		.execute(db)
+		.await;
+	match res {
+		Ok(_) => {
+			log::debug!("{selector}._domainkey.{sdid}: key will be usable after {usable_after}")
+		}
+		Err(err) => log::error!("{selector}._domainkey.{sdid}: {err}"),
+	}
+}
@@ -1,16 +1,28 @@
 mod action;
+mod address;
+mod admin;
 mod algorithm;
 mod canonicalization;
 mod config;
+mod config_file;
 mod db;
+mod dns;
+mod domain_config;
 mod entry;
 mod handshake;
 mod key;
 mod logs;
 mod message;
 mod parsed_message;
+mod quoted_printable;
+mod report;
+mod rfc2136;
+mod sdid;
+mod session;
 mod signature;
+mod spool;
 mod stdin_reader;
+mod zone_file;
 
 use action::{new_action, Action, ActionResult};
 use algorithm::Algorithm;
@@ -18,25 +30,38 @@ use canonicalization::CanonicalizationType;
 use futures::stream::FuturesUnordered;
 use futures::StreamExt;
 use key::key_rotation;
-use message::Message;
+use message::{Message, RejectedMessages};
+use report::ReportEvent;
+use sdid::SdidSource;
+use session::Session;
 use sqlx::SqlitePool;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::sync::Arc;
 use stdin_reader::StdinReader;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::RwLock;
 
 const DEFAULT_BUFF_SIZE: usize = 1024;
+const DEFAULT_HEADERS_SIZE: usize = 16 * 1024;
 const DEFAULT_CNF_ALGORITHM: Algorithm = Algorithm::Rsa2048Sha256;
 const DEFAULT_CNF_CANONICALIZATION_BODY: CanonicalizationType = CanonicalizationType::Relaxed;
 const DEFAULT_CNF_CANONICALIZATION_HEADER: CanonicalizationType = CanonicalizationType::Relaxed;
 const DEFAULT_CNF_CRYPTOPERIOD: u64 = 15552000;
 const DEFAULT_CNF_EXPIRATION: u64 = 1296000;
+const DEFAULT_CNF_FOLDING_WIDTH: usize = 78;
 const DEFAULT_CNF_HEADERS: &str = "from:reply-to:subject:date:to:cc";
+const DEFAULT_CNF_HEADERS_OVERSIGN: &str = "from:reply-to:subject:to:cc";
 const DEFAULT_CNF_HEADERS_OPT: &str = "resent-date:resent-from:resent-to:resent-cc:in-reply-to:references:list-id:list-help:list-unsubscribe:list-subscribe:list-post:list-owner:list-archive";
 const DEFAULT_CNF_KEY_DB: &str = "key-db.sqlite3";
+const DEFAULT_CNF_PUBLISH_DELAY: u64 = 3600;
 const DEFAULT_CNF_REVOCATION: u64 = 1728000;
+const DEFAULT_CNF_SDID_SOURCE: SdidSource = SdidSource::From;
+const DEFAULT_CNF_SPOOL_THRESHOLD: usize = 1024 * 1024;
 const DEFAULT_LIB_DIR: &str = env!("VARLIBDIR");
-const DEFAULT_MSG_SIZE: usize = 1024 * 1024;
+const DNS_RECORD_TTL: u32 = 3600;
+const DNS_UPDATE_TIMEOUT: u64 = 30;
+const FOLDING_WIDTH_MAX: usize = 998;
+const FOLDING_WIDTH_MIN: usize = 20;
 const KEY_CHECK_MIN_DELAY: u64 = 60 * 60 * 3;
 const LOG_LEVEL_ENV_VAR: &str = "OPENSMTPD_FILTER_DKIMOUT_LOG_LEVEL";
 const SIG_RETRY_NB_RETRY: usize = 10;
@@ -73,30 +98,66 @@ macro_rules! log_messages {
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
 	match config::Config::init() {
 		Ok(cnf) => {
+			if cnf.check_config() {
+				for warning in cnf.warnings() {
+					eprintln!("warning: {warning}");
+				}
+				println!("configuration OK");
+				return Ok(());
+			}
 			logs::init_log_system(&cnf);
 			log::debug!("{cnf:?}");
 			match db::init(&cnf).await {
-				Ok(pool) => main_loop(&cnf, &pool).await,
+				Ok(pool) => match cnf.command() {
+					Some(cmd) => {
+						if let Err(e) = admin::run(&pool, &cnf, cmd).await {
+							eprintln!("{e}");
+							std::process::exit(1);
+						}
+					}
+					None => main_loop(cnf, &pool).await,
+				},
 				Err(e) => eprintln!("{e}"),
 			}
 		}
-		Err(e) => eprintln!("{e}"),
+		Err(e) => {
+			eprintln!("{e}");
+			std::process::exit(1);
+		}
 	}
 	Ok(())
 }
 
-async fn main_loop(cnf: &config::Config, db: &SqlitePool) {
+async fn main_loop(cnf: config::Config, db: &SqlitePool) {
+	for warning in cnf.warnings() {
+		log::warn!("{warning}");
+	}
+	let mut cnf = Arc::new(cnf);
 	let mut actions = FuturesUnordered::new();
 	let mut reader = StdinReader::new();
 	let mut messages: HashMap<String, Message> = HashMap::new();
-	tokio::join!(handshake::read_config(&mut reader), key_rotation(db, cnf));
+	let mut sessions: HashMap<String, Session> = HashMap::new();
+	let rejected: RejectedMessages = Arc::new(RwLock::new(HashSet::new()));
+	let signal_lock = match signal(SignalKind::hangup()) {
+		Ok(s) => Some(Arc::new(RwLock::new(s))),
+		Err(err) => {
+			log::error!("unable to handle SIGHUP: {err}");
+			None
+		}
+	};
+	tokio::join!(handshake::read_config(&mut reader), key_rotation(db, &cnf));
 	handshake::register_filter();
 	log_messages!(messages);
 	let reader_lock = Arc::new(RwLock::new(reader));
+	let mut nb_background_actions = 1;
 	actions.push(new_action(Action::ReadLine(reader_lock.clone())));
-	actions.push(new_action(Action::RotateKeys((db, cnf))));
+	actions.push(new_action(Action::RotateKeys((db, cnf.clone()))));
+	if let Some(signal_lock) = &signal_lock {
+		actions.push(new_action(Action::ReadSignal(signal_lock.clone())));
+		nb_background_actions += 1;
+	}
 	loop {
-		if actions.len() <= 1 {
+		if actions.len() <= nb_background_actions {
 			break;
 		}
 		if let Some(action_res) = actions.next().await {
@@ -104,12 +165,40 @@ async fn main_loop(cnf: &config::Config, db: &SqlitePool) {
 				ActionResult::EndOfStream => {
 					log::debug!("end of input stream");
 				}
+				ActionResult::Hangup => {
+					log::info!("SIGHUP received, reloading the configuration");
+					if let Some(new_cnf) = reload_config(&cnf) {
+						cnf = Arc::new(new_cnf);
+						actions.push(new_action(Action::RotateKeysNow((db, cnf.clone()))));
+					}
+					if let Some(signal_lock) = &signal_lock {
+						actions.push(new_action(Action::ReadSignal(signal_lock.clone())));
+					}
+				}
 				ActionResult::KeyRotation => {
-					actions.push(new_action(Action::RotateKeys((db, cnf))));
+					actions.push(new_action(Action::RotateKeys((db, cnf.clone()))));
+				}
+				ActionResult::KeyRotationNow => {
+					log::debug!("key rotation after configuration reload done");
 				}
 				ActionResult::MessageSent(msg_id) => {
 					log::debug!("message removed: {msg_id}");
 				}
+				ActionResult::NewEntry(entry) if entry.is_commit() => {
+					let msg_id = entry.get_msg_id();
+					let is_rejected = rejected.write().await.remove(&msg_id);
+					log::debug!("commit: {msg_id}: rejected: {is_rejected}");
+					if let Err(err) = message::print_commit_result(
+						entry.get_session_id(),
+						entry.get_token(),
+						is_rejected,
+					)
+					.await
+					{
+						log::error!("{msg_id}: unable to write the commit result: {err}");
+					}
+					actions.push(new_action(Action::ReadLine(reader_lock.clone())));
+				}
 				ActionResult::NewEntry(entry) => {
 					let msg_id = entry.get_msg_id();
 					match messages.get_mut(&msg_id) {
@@ -120,23 +209,54 @@ async fn main_loop(cnf: &config::Config, db: &SqlitePool) {
 							} else {
 								log::debug!("message ready: {msg_id}");
 								if let Some(m) = messages.remove(&msg_id) {
-									actions.push(new_action(Action::SendMessage((db, cnf, m))));
+									actions.push(new_action(Action::SendMessage((
+										db,
+										rejected.clone(),
+										Box::new(m),
+									))));
 								}
 							}
 						}
 						None => {
-							let msg = Message::from_entry(&entry);
+							let session = sessions
+								.get(entry.get_session_id())
+								.cloned()
+								.unwrap_or_default();
+							let msg = Message::from_entry(&entry, session, cnf.clone());
 							log::debug!("new message: {msg_id}");
 							if !entry.is_end_of_message() {
 								messages.insert(msg_id.clone(), msg);
 							} else {
-								actions.push(new_action(Action::SendMessage((db, cnf, msg))));
+								actions.push(new_action(Action::SendMessage((
+									db,
+									rejected.clone(),
+									Box::new(msg),
+								))));
 							}
 						}
 					}
 					log_messages!(messages);
 					actions.push(new_action(Action::ReadLine(reader_lock.clone())));
 				}
+				ActionResult::NewReport(report) => {
+					let session_id = report.get_session_id().to_string();
+					match report.get_event() {
+						ReportEvent::LinkConnect { src } => {
+							log::debug!("new session: {session_id}");
+							sessions.insert(session_id, Session::new(src, cnf.local_networks()));
+						}
+						ReportEvent::LinkDisconnect => {
+							log::debug!("session closed: {session_id}");
+							sessions.remove(&session_id);
+						}
+						_ => {
+							if let Some(session) = sessions.get_mut(&session_id) {
+								session.update(&report);
+							}
+						}
+					}
+					actions.push(new_action(Action::ReadLine(reader_lock.clone())));
+				}
 				ActionResult::NewEntryError(err) => {
 					log::error!("invalid filter line: {err}");
 					actions.push(new_action(Action::ReadLine(reader_lock.clone())));
@@ -145,3 +265,23 @@ async fn main_loop(cnf: &config::Config, db: &SqlitePool) {
 		}
 	}
 }
+
+fn reload_config(old_cnf: &config::Config) -> Option<config::Config> {
+	match config::Config::init() {
+		Ok(cnf) => {
+			if cnf.key_data_base() != old_cnf.key_data_base() {
+				log::warn!("the key database cannot be changed without restarting the filter");
+			}
+			log::info!("configuration reloaded");
+			for warning in cnf.warnings() {
+				log::warn!("{warning}");
+			}
+			log::debug!("{cnf:?}");
+			Some(cnf)
+		}
+		Err(err) => {
+			log::error!("unable to reload the configuration: {err}");
+			None
+		}
+	}
+}
@@ -1,29 +1,58 @@
+use crate::algorithm::Algorithm;
 use crate::config::Config;
 use crate::entry::Entry;
 use crate::parsed_message::ParsedMessage;
-use crate::signature::Signature;
+use crate::sdid::{get_auid, get_sdid};
+use crate::session::Session;
+use crate::signature::{BodyHasher, Signature};
+use crate::spool::Spool;
 use anyhow::Result;
 use sqlx::SqlitePool;
-use tokio::io::{AsyncWriteExt, BufWriter};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufWriter};
+use tokio::sync::RwLock;
 
 pub const RETURN_SEP: &[u8] = b"|";
 pub const RETURN_START: &[u8] = b"filter-dataline|";
+/// Messages which could not be returned and must be rejected at commit.
+pub type RejectedMessages = Arc<RwLock<HashSet<String>>>;
+
+pub const RESULT_START: &[u8] = b"filter-result|";
+pub const RESULT_PROCEED: &[u8] = b"proceed";
+pub const RESULT_REJECT: &[u8] = b"reject|451 4.3.0 Temporary failure, please try again later";
 
 #[derive(Debug)]
 pub struct Message {
 	session_id: String,
 	token: String,
-	content: Vec<u8>,
+	headers: Vec<u8>,
+	in_body: bool,
+	sdid: Option<String>,
+	body: Spool,
+	body_hasher: Option<BodyHasher>,
+	spool_error: bool,
 	nb_lines: usize,
+	session: Session,
+	cnf: Arc<Config>,
 }
 
 impl Message {
-	pub fn from_entry(entry: &Entry) -> Self {
+	/// The configuration is kept for the whole message so a reload does not apply to a message
+	/// which is being received.
+	pub fn from_entry(entry: &Entry, session: Session, cnf: Arc<Config>) -> Self {
 		let mut ret = Self {
 			session_id: entry.get_session_id().to_string(),
 			token: entry.get_token().to_string(),
-			content: Vec::with_capacity(crate::DEFAULT_MSG_SIZE),
+			headers: Vec::with_capacity(crate::DEFAULT_HEADERS_SIZE),
+			in_body: false,
+			sdid: None,
+			body: Spool::new(cnf.spool_threshold()),
+			body_hasher: None,
+			spool_error: false,
 			nb_lines: 0,
+			session,
+			cnf,
 		};
 		if !entry.is_end_of_message() {
 			ret.append_line(entry.get_data());
@@ -31,76 +60,195 @@ impl Message {
 		ret
 	}
 
+	/// Only the headers are kept as is. The body is hashed as it is received and then spooled so
+	/// it can be returned after the signature.
 	pub fn append_line(&mut self, line: &[u8]) {
 		self.nb_lines += 1;
-		if self.content.len() + line.len() > self.content.capacity() {
-			self.content.reserve(crate::DEFAULT_MSG_SIZE);
-		}
-		self.content.extend_from_slice(line);
-		match line.last() {
-			Some(&c) => {
-				if c != b'\r' {
-					self.content.push(b'\r');
+		if self.in_body {
+			if let Some(body_hasher) = &mut self.body_hasher {
+				body_hasher.update(line);
+			}
+			if let Err(err) = self.body.write_line(line) {
+				if !self.spool_error {
+					log::error!(
+						"{}: unable to spool the message body: {err}",
+						get_msg_id(&self.session_id, &self.token)
+					);
 				}
+				self.spool_error = true;
 			}
-			None => {
-				self.content.push(b'\r');
+		} else {
+			let line = line.strip_suffix(b"\r").unwrap_or(line);
+			self.headers.extend_from_slice(line);
+			self.headers.extend_from_slice(b"\r\n");
+			if line.is_empty() {
+				self.end_headers();
 			}
 		}
-		self.content.push(b'\n');
 	}
 
 	pub fn nb_lines(&self) -> usize {
 		self.nb_lines
 	}
 
-	pub async fn sign_and_return(&self, db: &SqlitePool, cnf: &Config) -> String {
+	/// Once the headers have been received, the signing domain is known. The body hasher is
+	/// therefore set up to only use its body canonicalization and the hash algorithms of its
+	/// signing algorithms.
+	fn end_headers(&mut self) {
+		self.in_body = true;
+		let cnf = self.cnf.clone();
 		let msg_id = get_msg_id(&self.session_id, &self.token);
 		log::trace!(
-			"{msg_id}: content: {}",
-			crate::display_bytes!(&self.content)
+			"{msg_id}: headers: {}",
+			crate::display_bytes!(&self.headers)
 		);
-		match ParsedMessage::from_bytes(&self.content) {
-			Ok(parsed_msg) => {
-				log::trace!("mail parsed");
-				for h in &parsed_msg.headers {
-					log::trace!(
-						"ParsedMessage: header: raw: {}",
-						crate::display_bytes!(h.raw)
-					);
-					log::trace!(
-						"ParsedMessage: header: name: {}",
-						crate::display_bytes!(h.name)
-					);
-					log::trace!(
-						"ParsedMessage: header: value: {}",
-						crate::display_bytes!(h.value)
-					);
-				}
-				log::trace!(
-					"ParsedMessage: body: {}",
-					crate::display_bytes!(parsed_msg.body)
-				);
-				match Signature::new(db, cnf, &parsed_msg).await {
-					Ok(signature) => {
-						let sig_header = signature.get_header();
-						if let Err(err) = self.print_sig_header(&sig_header).await {
-							log::error!("{msg_id}: unable to add the signature header: {err}");
-						}
+		let policy = cnf.sign_policy();
+		if !policy.allows(&self.session) {
+			log::info!(
+				"{msg_id}: message from <{}> not signed, the session does not match the {policy} signing policy",
+				self.session.mail_from().unwrap_or_default()
+			);
+			return;
+		}
+		let parsed_msg = match ParsedMessage::from_bytes(&self.headers) {
+			Ok(parsed_msg) => parsed_msg,
+			Err(err) => {
+				log::error!("{msg_id}: unable to parse message: {err}");
+				return;
+			}
+		};
+		log::trace!("mail parsed");
+		for h in &parsed_msg.headers {
+			log::trace!(
+				"ParsedMessage: header: raw: {}",
+				crate::display_bytes!(h.raw)
+			);
+			log::trace!(
+				"ParsedMessage: header: name: {}",
+				crate::display_bytes!(h.name)
+			);
+			log::trace!(
+				"ParsedMessage: header: value: {}",
+				crate::display_bytes!(h.value)
+			);
+		}
+		match get_sdid(&cnf, &parsed_msg, &self.session) {
+			Ok((sdid, source)) => {
+				log::info!("{msg_id}: signing domain {sdid} taken from the {source}");
+				let mut hash_algorithms = Vec::with_capacity(2);
+				for algorithm in cnf.algorithms(&sdid) {
+					if !hash_algorithms.contains(&algorithm.hash_algorithm()) {
+						hash_algorithms.push(algorithm.hash_algorithm());
 					}
-					Err(err) => log::error!("{msg_id}: unable to sign message: {err}"),
 				}
+				let canonicalization = cnf.canonicalization(&sdid).body_alg();
+				self.body_hasher = Some(BodyHasher::new(canonicalization, &hash_algorithms));
+				self.sdid = Some(sdid);
 			}
-			Err(err) => {
-				log::error!("{msg_id}: unable to parse message: {err}");
+			Err(err) => log::error!("{msg_id}: unable to sign message: {err}"),
+		}
+	}
+
+	pub async fn sign_and_return(
+		&mut self,
+		db: &SqlitePool,
+		rejected: &RwLock<HashSet<String>>,
+	) -> String {
+		let msg_id = get_msg_id(&self.session_id, &self.token);
+		if !self.in_body {
+			self.end_headers();
+		}
+		if let Err(err) = self.body.flush() {
+			log::error!("{msg_id}: unable to spool the message body: {err}");
+			self.spool_error = true;
+		}
+		if self.spool_error {
+			// The message cannot be returned entirely. Rather than delivering a truncated message,
+			// an empty one is returned and it is rejected with a temporary failure at commit.
+			log::error!("{msg_id}: the message cannot be returned, it will be rejected");
+			self.reject(rejected).await;
+			return msg_id;
+		}
+		for (algorithm, sig_header) in self.signatures(db).await {
+			if let Err(err) = self.print_sig_header(&sig_header).await {
+				log::error!("{msg_id}: unable to add the {algorithm} signature header: {err}");
 			}
 		}
 		if let Err(err) = self.print_msg().await {
-			log::error!("{msg_id}: unable to write message: {err}");
+			log::error!("{msg_id}: unable to write message: {err}, it will be rejected");
+			self.reject(rejected).await;
 		}
 		msg_id
 	}
 
+	async fn reject(&self, rejected: &RwLock<HashSet<String>>) {
+		let msg_id = get_msg_id(&self.session_id, &self.token);
+		rejected.write().await.insert(msg_id.clone());
+		if let Err(err) = self.print_line(b".").await {
+			log::error!("{msg_id}: unable to write message: {err}");
+		}
+	}
+
+	async fn signatures(&self, db: &SqlitePool) -> Vec<(Algorithm, String)> {
+		let mut ret = Vec::new();
+		if let (Some(sdid), Some(body_hasher)) = (&self.sdid, &self.body_hasher) {
+			match ParsedMessage::from_bytes(&self.headers) {
+				Ok(parsed_msg) => {
+					for algorithm in self.cnf.algorithms(sdid) {
+						let sdid = crate::key::usable_sdid(db, &self.cnf, sdid, *algorithm).await;
+						if let Some(sig_header) = self
+							.sign(db, &parsed_msg, body_hasher, &sdid, *algorithm)
+							.await
+						{
+							ret.push((*algorithm, sig_header));
+						}
+					}
+				}
+				Err(err) => log::error!(
+					"{}: unable to parse message: {err}",
+					get_msg_id(&self.session_id, &self.token)
+				),
+			}
+		}
+		ret
+	}
+
+	async fn sign(
+		&self,
+		db: &SqlitePool,
+		parsed_msg: &ParsedMessage<'_>,
+		body_hasher: &BodyHasher,
+		sdid: &str,
+		algorithm: Algorithm,
+	) -> Option<String> {
+		let msg_id = get_msg_id(&self.session_id, &self.token);
+		let cnf = &self.cnf;
+		let auid = match get_auid(cnf, parsed_msg, sdid) {
+			Ok(auid) => auid,
+			Err(err) => {
+				log::warn!("{msg_id}: {err}, the i= tag is omitted");
+				None
+			}
+		};
+		match Signature::new(
+			db,
+			cnf,
+			parsed_msg,
+			body_hasher,
+			sdid,
+			auid.as_deref(),
+			algorithm,
+		)
+		.await
+		{
+			Ok(signature) => Some(signature.get_header()),
+			Err(err) => {
+				log::error!("{msg_id}: unable to sign message using {algorithm}: {err}");
+				None
+			}
+		}
+	}
+
 	async fn print_sig_header(&self, sig_header: &str) -> Result<()> {
 		for line in sig_header.split("\r\n") {
 			self.print_line(line.as_bytes()).await?;
@@ -109,16 +257,21 @@ impl Message {
 	}
 
 	async fn print_msg(&self) -> Result<()> {
-		let i = self.content.len() - 1;
-		for line in self.content[0..i].split(|&b| b == b'\n') {
-			self.print_line(line).await?;
+		if let Some(headers) = self.headers.strip_suffix(b"\n") {
+			for line in headers.split(|&b| b == b'\n') {
+				self.print_line(line).await?;
+			}
+		}
+		let mut lines = self.body.reader().await?.split(b'\n');
+		while let Some(line) = lines.next_segment().await? {
+			self.print_line(&line).await?;
 		}
 		self.print_line(b".").await?;
 		Ok(())
 	}
 
 	async fn print_line(&self, line: &[u8]) -> Result<()> {
-		let line = if line.ends_with(&[b'\r']) {
+		let line = if line.ends_with(b"\r") {
 			&line[..line.len() - 1]
 		} else {
 			line
@@ -136,6 +289,124 @@ impl Message {
 	}
 }
 
+/// Answers the commit phase of a message, which is rejected with a temporary failure if it could
+/// not be returned entirely.
+pub async fn print_commit_result(session_id: &str, token: &str, rejected: bool) -> Result<()> {
+	let result = if rejected {
+		RESULT_REJECT
+	} else {
+		RESULT_PROCEED
+	};
+	let mut stdout = BufWriter::new(tokio::io::stdout());
+	stdout.write_all(RESULT_START).await?;
+	stdout.write_all(session_id.as_bytes()).await?;
+	stdout.write_all(RETURN_SEP).await?;
+	stdout.write_all(token.as_bytes()).await?;
+	stdout.write_all(RETURN_SEP).await?;
+	stdout.write_all(result).await?;
+	stdout.write_all(b"\n").await?;
+	stdout.flush().await?;
+	Ok(())
+}
+
 pub fn get_msg_id(session_id: &str, token: &str) -> String {
 	format!("{session_id}.{token}")
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::algorithm::HashAlgorithm;
+
+	fn message(cnf: Arc<Config>, lines: &[&[u8]]) -> Message {
+		let mut msg = Message {
+			session_id: String::from("7a8b"),
+			token: String::from("c9d0"),
+			headers: Vec::new(),
+			in_body: false,
+			sdid: None,
+			body: Spool::new(cnf.spool_threshold()),
+			body_hasher: None,
+			spool_error: false,
+			nb_lines: 0,
+			session: Session::default(),
+			cnf,
+		};
+		for line in lines {
+			msg.append_line(line);
+		}
+		msg
+	}
+
+	#[test]
+	fn body_hasher() {
+		let cnf = Arc::new(Config::from_args(&[
+			"-a",
+			"ed25519-sha256",
+			"-c",
+			"relaxed/simple",
+			"-d",
+			"example.org",
+		]));
+		let msg = message(
+			cnf.clone(),
+			&[b"From: john@example.org\r", b"\r", b"Hello,  World \r"],
+		);
+		assert_eq!(msg.sdid.as_deref(), Some("example.org"));
+		let body_hasher = msg.body_hasher.unwrap();
+		let (_, length) = body_hasher
+			.body_hash(cnf.canonicalization("example.org"), HashAlgorithm::Sha256)
+			.unwrap();
+		assert_eq!(length, b"Hello,  World \r\n".len());
+		assert!(body_hasher
+			.body_hash("relaxed/relaxed".parse().unwrap(), HashAlgorithm::Sha256)
+			.is_err());
+		assert!(body_hasher
+			.body_hash(cnf.canonicalization("example.org"), HashAlgorithm::Sha1)
+			.is_err());
+	}
+
+	#[test]
+	fn unknown_domain() {
+		let cnf = Arc::new(Config::from_args(&["-d", "example.org"]));
+		let msg = message(cnf, &[b"From: john@example.com\r", b"\r", b"Hello\r"]);
+		assert!(msg.sdid.is_none());
+		assert!(msg.body_hasher.is_none());
+	}
+
+	#[tokio::test]
+	async fn reload_during_message() {
+		let db = crate::db::init_test_db().await;
+		let mut cnf = Arc::new(Config::from_args(&[
+			"-a",
+			"ed25519-sha256",
+			"-c",
+			"relaxed/simple",
+			"-d",
+			"example.org",
+			"-u",
+			"builtin:none",
+			"-w",
+			"0",
+		]));
+		crate::key::generate_key(&db, &cnf, "example.org", Algorithm::Ed25519Sha256)
+			.await
+			.unwrap();
+		let mut msg = message(cnf.clone(), &[b"From: john@example.org\r", b"\r"]);
+		cnf = Arc::new(Config::from_args(&[
+			"-a",
+			"rsa2048-sha256",
+			"-c",
+			"simple/relaxed",
+			"-d",
+			"example.org",
+		]));
+		msg.append_line(b"Hello\r");
+		assert_eq!(cnf.algorithms("example.org"), &[Algorithm::Rsa2048Sha256]);
+		let signatures = msg.signatures(&db).await;
+		assert_eq!(signatures.len(), 1);
+		let (algorithm, sig_header) = &signatures[0];
+		assert_eq!(*algorithm, Algorithm::Ed25519Sha256);
+		assert!(sig_header.contains("c=relaxed/simple;"));
+	}
+}
@@ -2,13 +2,14 @@ use anyhow::{anyhow, Result};
 
 pub struct ParsedMessage<'a> {
 	pub headers: Vec<ParsedHeader<'a>>,
-	pub body: &'a [u8],
 }
 
 impl<'a> ParsedMessage<'a> {
+	/// Parses the headers of a message. Anything after the empty line which ends the headers is
+	/// ignored.
 	pub fn from_bytes(data: &'a [u8]) -> Result<Self> {
-		let (mut raw_headers, body) = match data.windows(4).position(|w| w == b"\r\n\r\n") {
-			Some(body_index) => (&data[..body_index + 2], &data[body_index + 4..]),
+		let mut raw_headers = match data.windows(4).position(|w| w == b"\r\n\r\n") {
+			Some(body_index) => &data[..body_index + 2],
 			None => return Err(anyhow!("message body not found")),
 		};
 		let mut headers = Vec::with_capacity(128);
@@ -19,7 +20,7 @@ impl<'a> ParsedMessage<'a> {
 			raw_headers = &raw_headers[end_index..];
 		}
 		headers.shrink_to_fit();
-		Ok(Self { headers, body })
+		Ok(Self { headers })
 	}
 }
 
@@ -68,3 +69,23 @@ impl<'a> ParsedHeader<'a> {
 		})
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn repeated_headers() {
+		let msg = ParsedMessage::from_bytes(include_bytes!("../tests/test_02.msg")).unwrap();
+		assert_eq!(msg.headers.len(), 14);
+		let received: Vec<&ParsedHeader> = msg
+			.headers
+			.iter()
+			.filter(|h| h.name_lower == "received")
+			.collect();
+		assert_eq!(received.len(), 3);
+		assert!(received[0].value.starts_with(b" from mx2.example.net"));
+		assert!(received[2].value.starts_with(b" from localhost"));
+		assert!(received[2].value.ends_with(b"11:01:12 +0100\r\n"));
+	}
+}
@@ -0,0 +1,52 @@
+// RFC 6376, section 2.11
+pub fn encode(data: &[u8]) -> String {
+	encode_except(data, &[])
+}
+
+// RFC 6376, section 3.5: vertical bars are used to separate the copied header fields in the z=
+// tag and must therefore be encoded.
+pub fn encode_copied_header(data: &[u8]) -> String {
+	encode_except(data, b"|")
+}
+
+fn encode_except(data: &[u8], unsafe_chars: &[u8]) -> String {
+	let mut ret = String::with_capacity(data.len() * 3);
+	for &c in data {
+		if is_safe_char(c) && !unsafe_chars.contains(&c) {
+			ret.push(c as char);
+		} else {
+			ret += &format!("={c:02X}");
+		}
+	}
+	ret
+}
+
+fn is_safe_char(c: u8) -> bool {
+	(0x21..=0x3a).contains(&c) || c == 0x3c || (0x3e..=0x7e).contains(&c)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn encode_printable() {
+		assert_eq!(encode(b"Hello,World!"), "Hello,World!");
+	}
+
+	#[test]
+	fn encode_special() {
+		assert_eq!(encode(b"a b;c=d\te|f"), "a=20b=3Bc=3Dd=09e|f");
+	}
+
+	#[test]
+	fn encode_copied_header_bar() {
+		assert_eq!(encode(b"a|b c"), "a|b=20c");
+		assert_eq!(encode_copied_header(b"a|b c"), "a=7Cb=20c");
+	}
+
+	#[test]
+	fn encode_non_ascii() {
+		assert_eq!(encode("é".as_bytes()), "=C3=A9");
+	}
+}
@@ -0,0 +1,191 @@
+use anyhow::{anyhow, Result};
+use nom::bytes::complete::{tag, take_till, take_while1};
+use nom::IResult;
+
+pub const REPORT_TAG: &[u8] = b"report|";
+pub const REPORT_EVENTS: &[&str] = &[
+	"link-connect",
+	"link-auth",
+	"tx-mail-from",
+	"link-disconnect",
+];
+
+#[derive(Debug)]
+pub enum ReportEvent {
+	LinkConnect { src: String },
+	LinkAuth { success: bool },
+	TxMailFrom { address: String, success: bool },
+	LinkDisconnect,
+}
+
+#[derive(Debug)]
+pub struct Report {
+	session_id: String,
+	event: ReportEvent,
+}
+
+impl Report {
+	pub fn get_session_id(&self) -> &str {
+		&self.session_id
+	}
+
+	pub fn get_event(&self) -> &ReportEvent {
+		&self.event
+	}
+
+	pub fn from_bytes(input: &[u8]) -> Result<Report> {
+		let (_, (version, event, session_id, params)) =
+			parse_report(input).map_err(|e| anyhow!("parsing error: {e}"))?;
+		let params: Vec<&str> = params.split('|').collect();
+		// Since version 0.6, the result comes before values which may contain a pipe.
+		let result_first = result_first(&version);
+		let event = match event.as_str() {
+			"link-connect" => ReportEvent::LinkConnect {
+				src: get_param(&params, 2)?.to_string(),
+			},
+			"link-auth" => {
+				let result = if result_first {
+					get_param(&params, 0)?
+				} else {
+					get_param(&params, params.len() - 1)?
+				};
+				ReportEvent::LinkAuth {
+					success: result == "pass",
+				}
+			}
+			"tx-mail-from" => {
+				let rest = params.get(1..).unwrap_or_default().join("|");
+				let (result, address) = if result_first {
+					rest.split_once('|')
+				} else {
+					rest.rsplit_once('|').map(|(a, r)| (r, a))
+				}
+				.ok_or(anyhow!("missing report parameter"))?;
+				ReportEvent::TxMailFrom {
+					address: address.to_string(),
+					success: result == "ok",
+				}
+			}
+			"link-disconnect" => ReportEvent::LinkDisconnect,
+			_ => return Err(anyhow!("{event}: unsupported report event")),
+		};
+		Ok(Report { session_id, event })
+	}
+}
+
+fn result_first(version: &str) -> bool {
+	let mut numbers = version.split('.').map(|n| n.parse::<u32>().unwrap_or(0));
+	let major = numbers.next().unwrap_or(0);
+	let minor = numbers.next().unwrap_or(0);
+	(major, minor) >= (0, 6)
+}
+
+fn get_param<'a>(params: &[&'a str], index: usize) -> Result<&'a str> {
+	params
+		.get(index)
+		.copied()
+		.ok_or(anyhow!("missing report parameter"))
+}
+
+fn is_eol(c: u8) -> bool {
+	c == b'\n'
+}
+
+fn is_parameter_char(c: u8) -> bool {
+	!(c as char).is_control() && (c as char) != '|'
+}
+
+fn parse_string_parameter(input: &[u8]) -> IResult<&[u8], String> {
+	let (input, s) = take_while1(is_parameter_char)(input)?;
+	Ok((input, String::from_utf8_lossy(s).to_string()))
+}
+
+fn parse_delimiter(input: &[u8]) -> IResult<&[u8], &[u8]> {
+	tag("|")(input)
+}
+
+fn parse_report(input: &[u8]) -> IResult<&[u8], (String, String, String, String)> {
+	let (input, _type) = tag("report")(input)?;
+	let (input, _) = parse_delimiter(input)?;
+	let (input, version) = parse_string_parameter(input)?;
+	let (input, _) = parse_delimiter(input)?;
+	let (input, _timestamp) = parse_string_parameter(input)?;
+	let (input, _) = parse_delimiter(input)?;
+	let (input, _subsystem) = tag("smtp-in")(input)?;
+	let (input, _) = parse_delimiter(input)?;
+	let (input, event) = parse_string_parameter(input)?;
+	let (input, _) = parse_delimiter(input)?;
+	let (input, session_id) = parse_string_parameter(input)?;
+	let (input, params) = take_till(is_eol)(input)?;
+	let params = params.strip_prefix(b"|").unwrap_or(params);
+	let params = String::from_utf8_lossy(params).to_string();
+	Ok((input, (version, event, session_id, params)))
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn link_connect() {
+		let line = b"report|0.7|1576146008.006099|smtp-in|link-connect|7641df9771b4ed00|mail.example.com|pass|199.185.178.25:33174|45.77.67.80:25\n";
+		let report = Report::from_bytes(line).unwrap();
+		assert_eq!(report.get_session_id(), "7641df9771b4ed00");
+		match report.get_event() {
+			ReportEvent::LinkConnect { src } => assert_eq!(src, "199.185.178.25:33174"),
+			e => panic!("invalid event: {e:?}"),
+		}
+	}
+
+	#[test]
+	fn link_auth() {
+		let line =
+			b"report|0.7|1576146008.006099|smtp-in|link-auth|7641df9771b4ed00|pass|user|name\n";
+		let report = Report::from_bytes(line).unwrap();
+		assert!(matches!(
+			report.get_event(),
+			ReportEvent::LinkAuth { success: true }
+		));
+		let line = b"report|0.5|1576146008.006099|smtp-in|link-auth|7641df9771b4ed00|pass|fail\n";
+		let report = Report::from_bytes(line).unwrap();
+		assert!(matches!(
+			report.get_event(),
+			ReportEvent::LinkAuth { success: false }
+		));
+	}
+
+	#[test]
+	fn tx_mail_from() {
+		let line = b"report|0.7|1576146008.006099|smtp-in|tx-mail-from|7641df9771b4ed00|1ef1c203|ok|a|b@example.org\n";
+		let report = Report::from_bytes(line).unwrap();
+		match report.get_event() {
+			ReportEvent::TxMailFrom { address, success } => {
+				assert_eq!(address, "a|b@example.org");
+				assert!(success);
+			}
+			e => panic!("invalid event: {e:?}"),
+		}
+		let line = b"report|0.5|1576146008.006099|smtp-in|tx-mail-from|7641df9771b4ed00|1ef1c203|a@example.org|permfail\n";
+		let report = Report::from_bytes(line).unwrap();
+		match report.get_event() {
+			ReportEvent::TxMailFrom { address, success } => {
+				assert_eq!(address, "a@example.org");
+				assert!(!success);
+			}
+			e => panic!("invalid event: {e:?}"),
+		}
+	}
+
+	#[test]
+	fn invalid() {
+		assert!(Report::from_bytes(
+			b"report|0.7|1576146008.006099|smtp-in|link-connect|7641df9771b4ed00\n"
+		)
+		.is_err());
+		assert!(Report::from_bytes(b"report|0.7|1576146008.006099|smtp-in|tx-rcpt-to|7641df9771b4ed00|1ef1c203|ok|a@example.org\n").is_err());
+		assert!(Report::from_bytes(
+			b"filter|0.7|1576146008.006099|smtp-in|data-line|7641df9771b4ed00|1ef1c203|test\n"
+		)
+		.is_err());
+	}
+}
@@ -0,0 +1,662 @@
+use anyhow::{anyhow, Result};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use sqlx::types::time::OffsetDateTime;
+use std::ops::Range;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+
+const CLASS_ANY: u16 = 255;
+const CLASS_IN: u16 = 1;
+const HEADER_LEN: usize = 12;
+const MAX_POINTERS: usize = 128;
+const OPCODE_QUERY: u16 = 0;
+const OPCODE_UPDATE: u16 = 5 << 11;
+const RCODE_MASK: u16 = 0x000f;
+const RCODE_NOERROR: u16 = 0;
+const RCODE_NXDOMAIN: u16 = 3;
+const FLAG_QR: u16 = 0x8000;
+const TSIG_ALGORITHM: &str = "hmac-sha256";
+const TSIG_FUDGE: u16 = 300;
+const TXT_STRING_MAX_LEN: usize = 255;
+const TYPE_SOA: u16 = 6;
+const TYPE_TSIG: u16 = 250;
+const TYPE_TXT: u16 = 16;
+
+#[derive(Clone, Debug)]
+pub struct Rfc2136Config {
+	server: String,
+	key_name: String,
+	secret: Vec<u8>,
+}
+
+impl Rfc2136Config {
+	pub fn new(server: &str, key_name: &str, secret: Vec<u8>) -> Self {
+		Self {
+			server: server.to_string(),
+			key_name: key_name.trim_end_matches('.').to_lowercase(),
+			secret,
+		}
+	}
+}
+
+enum Update<'a> {
+	Add(&'a str),
+	Delete,
+}
+
+struct Record {
+	name: String,
+	rtype: u16,
+	start: usize,
+	rdata: Range<usize>,
+}
+
+struct Tsig {
+	key_name: String,
+	algorithm: String,
+	time_signed: u64,
+	fudge: u16,
+	mac: Vec<u8>,
+	original_id: u16,
+	error: u16,
+	other_data: Vec<u8>,
+}
+
+pub async fn add_record(cnf: &Rfc2136Config, name: &str, txt: &str) -> Result<()> {
+	let zone = find_zone(cnf, name).await?;
+	send_update(cnf, &zone, name, Update::Add(txt)).await
+}
+
+pub async fn delete_record(cnf: &Rfc2136Config, name: &str) -> Result<()> {
+	let zone = find_zone(cnf, name).await?;
+	send_update(cnf, &zone, name, Update::Delete).await
+}
+
+/// The zone is the owner of the SOA record the primary server returns, either in the answer or
+/// in the authority section, when queried for the SOA record of the name to update.
+async fn find_zone(cnf: &Rfc2136Config, name: &str) -> Result<String> {
+	let msg = build_soa_query(rand::random::<u16>(), name)?;
+	let response = send_message(cnf, msg)
+		.await
+		.map_err(|e| anyhow!("{name}: {e}"))?;
+	match get_rcode(&response) {
+		RCODE_NOERROR | RCODE_NXDOMAIN => {}
+		rcode => return Err(anyhow!("{name}: SOA lookup failed: {}", rcode_name(rcode))),
+	}
+	let [answer, authority, _] = parse_records(&response).map_err(|e| anyhow!("{name}: {e}"))?;
+	let lower_name = name.trim_end_matches('.').to_lowercase();
+	let zone = answer
+		.into_iter()
+		.chain(authority)
+		.filter(|r| r.rtype == TYPE_SOA)
+		.map(|r| r.name)
+		.find(|zone| lower_name == *zone || lower_name.ends_with(&format!(".{zone}")))
+		.ok_or(anyhow!("{name}: zone not found"))?;
+	log::debug!("{name}: the record is in the {zone} zone");
+	Ok(zone)
+}
+
+async fn send_update(
+	cnf: &Rfc2136Config,
+	zone: &str,
+	name: &str,
+	update: Update<'_>,
+) -> Result<()> {
+	let msg = build_update(rand::random::<u16>(), zone, name, &update)?;
+	let response = send_message(cnf, msg)
+		.await
+		.map_err(|e| anyhow!("{name}: {e}"))?;
+	match get_rcode(&response) {
+		RCODE_NOERROR => {
+			log::info!("{name}: DNS record updated on {}", cnf.server);
+			Ok(())
+		}
+		rcode => Err(anyhow!("{name}: DNS update refused: {}", rcode_name(rcode))),
+	}
+}
+
+/// Signs the message, sends it and returns the response once authenticated.
+async fn send_message(cnf: &Rfc2136Config, mut msg: Vec<u8>) -> Result<Vec<u8>> {
+	let id = u16::from_be_bytes([msg[0], msg[1]]);
+	let time_signed = OffsetDateTime::now_utc().unix_timestamp() as u64;
+	let request_mac = sign_message(cnf, &mut msg, None, time_signed)?;
+	let delay = Duration::from_secs(crate::DNS_UPDATE_TIMEOUT);
+	let response = timeout(delay, exchange(&cnf.server, &msg))
+		.await
+		.map_err(|_| anyhow!("DNS request timed out"))??;
+	check_response(cnf, id, &request_mac, &response)?;
+	Ok(response)
+}
+
+async fn exchange(server: &str, msg: &[u8]) -> Result<Vec<u8>> {
+	let mut stream = TcpStream::connect(server)
+		.await
+		.map_err(|e| anyhow!("{server}: {e}"))?;
+	stream.write_all(&(msg.len() as u16).to_be_bytes()).await?;
+	stream.write_all(msg).await?;
+	stream.flush().await?;
+	let len = stream.read_u16().await? as usize;
+	let mut response = vec![0; len];
+	stream.read_exact(&mut response).await?;
+	Ok(response)
+}
+
+// RFC 8945, section 5.3
+fn check_response(cnf: &Rfc2136Config, id: u16, request_mac: &[u8], response: &[u8]) -> Result<()> {
+	if response.len() < HEADER_LEN {
+		return Err(anyhow!("DNS response is too short"));
+	}
+	let resp_id = u16::from_be_bytes([response[0], response[1]]);
+	let flags = u16::from_be_bytes([response[2], response[3]]);
+	if resp_id != id || flags & FLAG_QR == 0 {
+		return Err(anyhow!("invalid DNS response"));
+	}
+	let [_, _, additional] = parse_records(response)?;
+	let record = match additional.last() {
+		Some(record) if record.rtype == TYPE_TSIG => record,
+		_ => {
+			return Err(anyhow!(
+				"unsigned DNS response: {}",
+				rcode_name(get_rcode(response))
+			))
+		}
+	};
+	let tsig = parse_tsig(response, record)?;
+	if tsig.key_name != cnf.key_name || tsig.algorithm != TSIG_ALGORITHM {
+		return Err(anyhow!(
+			"DNS response signed with an unknown key: {} ({})",
+			tsig.key_name,
+			tsig.algorithm
+		));
+	}
+	if tsig.error != 0 {
+		return Err(anyhow!("TSIG error: {}", rcode_name(tsig.error)));
+	}
+	let mut unsigned = response[..record.start].to_vec();
+	unsigned[..2].copy_from_slice(&tsig.original_id.to_be_bytes());
+	let arcount = u16::from_be_bytes([unsigned[10], unsigned[11]]) - 1;
+	unsigned[10..12].copy_from_slice(&arcount.to_be_bytes());
+	compute_mac(cnf, Some(request_mac), &unsigned, &tsig)?
+		.verify_slice(&tsig.mac)
+		.map_err(|_| anyhow!("invalid TSIG signature on the DNS response"))?;
+	let now = OffsetDateTime::now_utc().unix_timestamp() as u64;
+	if now.abs_diff(tsig.time_signed) > tsig.fudge as u64 {
+		return Err(anyhow!(
+			"the DNS response has been signed at an invalid time"
+		));
+	}
+	Ok(())
+}
+
+fn get_rcode(response: &[u8]) -> u16 {
+	u16::from_be_bytes([response[2], response[3]]) & RCODE_MASK
+}
+
+fn rcode_name(rcode: u16) -> String {
+	match rcode {
+		0 => String::from("NOERROR"),
+		1 => String::from("FORMERR"),
+		2 => String::from("SERVFAIL"),
+		3 => String::from("NXDOMAIN"),
+		4 => String::from("NOTIMP"),
+		5 => String::from("REFUSED"),
+		6 => String::from("YXDOMAIN"),
+		7 => String::from("YXRRSET"),
+		8 => String::from("NXRRSET"),
+		9 => String::from("NOTAUTH"),
+		10 => String::from("NOTZONE"),
+		16 => String::from("BADSIG"),
+		17 => String::from("BADKEY"),
+		18 => String::from("BADTIME"),
+		22 => String::from("BADTRUNC"),
+		_ => format!("RCODE {rcode}"),
+	}
+}
+
+fn build_soa_query(id: u16, name: &str) -> Result<Vec<u8>> {
+	let mut msg = Vec::with_capacity(crate::DEFAULT_BUFF_SIZE);
+
+	// Header: one question, no answer, no authority, no additional record
+	msg.extend_from_slice(&id.to_be_bytes());
+	msg.extend_from_slice(&OPCODE_QUERY.to_be_bytes());
+	for count in [1_u16, 0, 0, 0] {
+		msg.extend_from_slice(&count.to_be_bytes());
+	}
+
+	// Question section
+	push_name(&mut msg, name)?;
+	msg.extend_from_slice(&TYPE_SOA.to_be_bytes());
+	msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+	Ok(msg)
+}
+
+fn build_update(id: u16, zone: &str, name: &str, update: &Update<'_>) -> Result<Vec<u8>> {
+	let mut msg = Vec::with_capacity(crate::DEFAULT_BUFF_SIZE);
+
+	// Header: one zone, no prerequisite, one update, no additional record
+	msg.extend_from_slice(&id.to_be_bytes());
+	msg.extend_from_slice(&OPCODE_UPDATE.to_be_bytes());
+	for count in [1_u16, 0, 1, 0] {
+		msg.extend_from_slice(&count.to_be_bytes());
+	}
+
+	// Zone section
+	push_name(&mut msg, zone)?;
+	msg.extend_from_slice(&TYPE_SOA.to_be_bytes());
+	msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+
+	// Update section
+	push_name(&mut msg, name)?;
+	msg.extend_from_slice(&TYPE_TXT.to_be_bytes());
+	match update {
+		Update::Add(txt) => {
+			let rdata = txt_rdata(txt);
+			msg.extend_from_slice(&CLASS_IN.to_be_bytes());
+			msg.extend_from_slice(&crate::DNS_RECORD_TTL.to_be_bytes());
+			msg.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+			msg.extend_from_slice(&rdata);
+		}
+		Update::Delete => {
+			msg.extend_from_slice(&CLASS_ANY.to_be_bytes());
+			msg.extend_from_slice(&0_u32.to_be_bytes());
+			msg.extend_from_slice(&0_u16.to_be_bytes());
+		}
+	}
+	Ok(msg)
+}
+
+/// Adds a TSIG record to the message and returns its MAC. The MAC of the request is required to
+/// sign a response.
+fn sign_message(
+	cnf: &Rfc2136Config,
+	msg: &mut Vec<u8>,
+	request_mac: Option<&[u8]>,
+	time_signed: u64,
+) -> Result<Vec<u8>> {
+	let mut tsig = Tsig {
+		key_name: cnf.key_name.clone(),
+		algorithm: TSIG_ALGORITHM.to_string(),
+		time_signed,
+		fudge: TSIG_FUDGE,
+		mac: Vec::new(),
+		original_id: u16::from_be_bytes([msg[0], msg[1]]),
+		error: 0,
+		other_data: Vec::new(),
+	};
+	tsig.mac = compute_mac(cnf, request_mac, msg, &tsig)?
+		.finalize()
+		.into_bytes()
+		.to_vec();
+
+	let mut rdata = Vec::with_capacity(128);
+	push_name(&mut rdata, &tsig.algorithm)?;
+	rdata.extend_from_slice(&tsig.time_signed.to_be_bytes()[2..]);
+	rdata.extend_from_slice(&tsig.fudge.to_be_bytes());
+	rdata.extend_from_slice(&(tsig.mac.len() as u16).to_be_bytes());
+	rdata.extend_from_slice(&tsig.mac);
+	rdata.extend_from_slice(&tsig.original_id.to_be_bytes());
+	rdata.extend_from_slice(&tsig.error.to_be_bytes());
+	rdata.extend_from_slice(&(tsig.other_data.len() as u16).to_be_bytes());
+	rdata.extend_from_slice(&tsig.other_data);
+
+	push_name(msg, &tsig.key_name)?;
+	msg.extend_from_slice(&TYPE_TSIG.to_be_bytes());
+	msg.extend_from_slice(&CLASS_ANY.to_be_bytes());
+	msg.extend_from_slice(&0_u32.to_be_bytes());
+	msg.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+	msg.extend_from_slice(&rdata);
+	let arcount = u16::from_be_bytes([msg[10], msg[11]]) + 1;
+	msg[10..12].copy_from_slice(&arcount.to_be_bytes());
+	Ok(tsig.mac)
+}
+
+// RFC 8945, sections 4.3.1 to 4.3.3
+fn compute_mac(
+	cnf: &Rfc2136Config,
+	request_mac: Option<&[u8]>,
+	msg: &[u8],
+	tsig: &Tsig,
+) -> Result<Hmac<Sha256>> {
+	let mut key_name = Vec::with_capacity(256);
+	push_name(&mut key_name, &tsig.key_name)?;
+	let mut algorithm = Vec::with_capacity(16);
+	push_name(&mut algorithm, &tsig.algorithm)?;
+
+	let mut mac = Hmac::<Sha256>::new_from_slice(&cnf.secret)?;
+	if let Some(request_mac) = request_mac {
+		mac.update(&(request_mac.len() as u16).to_be_bytes());
+		mac.update(request_mac);
+	}
+	mac.update(msg);
+	mac.update(&key_name);
+	mac.update(&CLASS_ANY.to_be_bytes());
+	mac.update(&0_u32.to_be_bytes());
+	mac.update(&algorithm);
+	mac.update(&tsig.time_signed.to_be_bytes()[2..]);
+	mac.update(&tsig.fudge.to_be_bytes());
+	mac.update(&tsig.error.to_be_bytes());
+	mac.update(&(tsig.other_data.len() as u16).to_be_bytes());
+	mac.update(&tsig.other_data);
+	Ok(mac)
+}
+
+/// Returns the records of the answer, authority and additional sections.
+fn parse_records(msg: &[u8]) -> Result<[Vec<Record>; 3]> {
+	let nb_questions = read_u16(msg, 4)?;
+	let mut pos = HEADER_LEN;
+	for _ in 0..nb_questions {
+		pos = read_name(msg, pos)?.1 + 4;
+	}
+	let mut ret: [Vec<Record>; 3] = Default::default();
+	for (i, section) in ret.iter_mut().enumerate() {
+		for _ in 0..read_u16(msg, 6 + 2 * i)? {
+			let start = pos;
+			let (name, rdata_pos) = read_name(msg, pos)?;
+			let rtype = read_u16(msg, rdata_pos)?;
+			let rdata_start = rdata_pos + 10;
+			let rdata_end = rdata_start + read_u16(msg, rdata_pos + 8)? as usize;
+			if rdata_end > msg.len() {
+				return Err(anyhow!("truncated DNS message"));
+			}
+			section.push(Record {
+				name,
+				rtype,
+				start,
+				rdata: rdata_start..rdata_end,
+			});
+			pos = rdata_end;
+		}
+	}
+	Ok(ret)
+}
+
+fn parse_tsig(msg: &[u8], record: &Record) -> Result<Tsig> {
+	let rdata = &msg[..record.rdata.end];
+	let (algorithm, pos) = read_name(rdata, record.rdata.start)?;
+	let time_signed = rdata
+		.get(pos..pos + 6)
+		.ok_or(anyhow!("truncated TSIG record"))?
+		.iter()
+		.fold(0, |acc, &b| (acc << 8) | b as u64);
+	let fudge = read_u16(rdata, pos + 6)?;
+	let mac_len = read_u16(rdata, pos + 8)? as usize;
+	let pos = pos + 10;
+	let mac = rdata
+		.get(pos..pos + mac_len)
+		.ok_or(anyhow!("truncated TSIG record"))?
+		.to_vec();
+	let pos = pos + mac_len;
+	let original_id = read_u16(rdata, pos)?;
+	let error = read_u16(rdata, pos + 2)?;
+	let other_len = read_u16(rdata, pos + 4)? as usize;
+	let other_data = rdata
+		.get(pos + 6..pos + 6 + other_len)
+		.ok_or(anyhow!("truncated TSIG record"))?
+		.to_vec();
+	Ok(Tsig {
+		key_name: record.name.clone(),
+		algorithm,
+		time_signed,
+		fudge,
+		mac,
+		original_id,
+		error,
+		other_data,
+	})
+}
+
+/// Returns the lowercase name starting at the given position, following compression pointers,
+/// and the position right after it.
+fn read_name(msg: &[u8], mut pos: usize) -> Result<(String, usize)> {
+	let mut labels = Vec::new();
+	let mut end = None;
+	for _ in 0..MAX_POINTERS {
+		let len = *msg.get(pos).ok_or(anyhow!("truncated DNS message"))? as usize;
+		match len & 0xc0 {
+			0x00 if len == 0 => {
+				let name = labels.join(".").to_lowercase();
+				return Ok((name, end.unwrap_or(pos + 1)));
+			}
+			0x00 => {
+				let label = msg
+					.get(pos + 1..pos + 1 + len)
+					.ok_or(anyhow!("truncated DNS message"))?;
+				labels.push(String::from_utf8_lossy(label).to_string());
+				pos += len + 1;
+			}
+			0xc0 => {
+				end.get_or_insert(pos + 2);
+				pos = (read_u16(msg, pos)? & 0x3fff) as usize;
+			}
+			_ => return Err(anyhow!("invalid DNS name")),
+		}
+	}
+	Err(anyhow!("invalid DNS name"))
+}
+
+fn read_u16(msg: &[u8], pos: usize) -> Result<u16> {
+	msg.get(pos..pos + 2)
+		.map(|b| u16::from_be_bytes([b[0], b[1]]))
+		.ok_or(anyhow!("truncated DNS message"))
+}
+
+fn push_name(buff: &mut Vec<u8>, name: &str) -> Result<()> {
+	let name = name.trim_end_matches('.');
+	if name.len() > 253 {
+		return Err(anyhow!("{name}: domain name is too long"));
+	}
+	for label in name.split('.') {
+		if label.is_empty() || label.len() > 63 {
+			return Err(anyhow!("{name}: invalid domain name"));
+		}
+		buff.push(label.len() as u8);
+		buff.extend_from_slice(label.as_bytes());
+	}
+	buff.push(0);
+	Ok(())
+}
+
+fn txt_rdata(txt: &str) -> Vec<u8> {
+	let mut rdata = Vec::with_capacity(txt.len() + txt.len() / TXT_STRING_MAX_LEN + 1);
+	for chunk in txt.as_bytes().chunks(TXT_STRING_MAX_LEN) {
+		rdata.push(chunk.len() as u8);
+		rdata.extend_from_slice(chunk);
+	}
+	rdata
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tokio::net::TcpListener;
+
+	const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+
+	fn get_config(server: &str) -> Rfc2136Config {
+		Rfc2136Config::new(server, "Update-Key.example.org.", SECRET.to_vec())
+	}
+
+	fn now() -> u64 {
+		OffsetDateTime::now_utc().unix_timestamp() as u64
+	}
+
+	fn get_request_mac(query: &[u8]) -> Vec<u8> {
+		let [_, _, additional] = parse_records(query).unwrap();
+		parse_tsig(query, additional.last().unwrap()).unwrap().mac
+	}
+
+	// Builds the response to a query with the given RCODE and, if any, an authority SOA record.
+	fn build_response(query: &[u8], rcode: u16, soa: Option<&str>) -> Vec<u8> {
+		let mut response = query[..HEADER_LEN].to_vec();
+		response[2] |= 0x80;
+		response[3] = rcode as u8;
+		response[6..].iter_mut().for_each(|b| *b = 0);
+		let question_end = read_name(query, HEADER_LEN).unwrap().1 + 4;
+		response.extend_from_slice(&query[HEADER_LEN..question_end]);
+		if let Some(zone) = soa {
+			response[9] = 1;
+			push_name(&mut response, zone).unwrap();
+			response.extend_from_slice(&TYPE_SOA.to_be_bytes());
+			response.extend_from_slice(&CLASS_IN.to_be_bytes());
+			response.extend_from_slice(&3600_u32.to_be_bytes());
+			let mut rdata = Vec::new();
+			push_name(&mut rdata, &format!("ns.{zone}")).unwrap();
+			push_name(&mut rdata, &format!("hostmaster.{zone}")).unwrap();
+			rdata.extend_from_slice(&[0; 20]);
+			response.extend_from_slice(&(rdata.len() as u16).to_be_bytes());
+			response.extend_from_slice(&rdata);
+		}
+		response
+	}
+
+	#[test]
+	fn encode_name() {
+		let mut buff = Vec::new();
+		push_name(&mut buff, "dkim._domainkey.example.org.").unwrap();
+		assert_eq!(buff, b"\x04dkim\x0a_domainkey\x07example\x03org\x00");
+	}
+
+	#[test]
+	fn encode_invalid_name() {
+		let mut buff = Vec::new();
+		assert!(push_name(&mut buff, "example..org").is_err());
+	}
+
+	#[test]
+	fn decode_compressed_name() {
+		let msg = b"\x07example\x03org\x00\x04dkim\x0a_domainkey\xc0\x00\xc0\x12";
+		assert_eq!(read_name(msg, 0).unwrap(), ("example.org".into(), 13));
+		assert_eq!(
+			read_name(msg, 13).unwrap(),
+			("dkim._domainkey.example.org".into(), 31)
+		);
+		let msg = b"\xc0\x00";
+		assert!(read_name(msg, 0).is_err());
+	}
+
+	#[test]
+	fn encode_long_txt() {
+		let txt = "a".repeat(300);
+		let rdata = txt_rdata(&txt);
+		assert_eq!(rdata.len(), 302);
+		assert_eq!(rdata[0], 255);
+		assert_eq!(rdata[256], 45);
+	}
+
+	#[test]
+	fn tsig_mac() {
+		let cnf = get_config("127.0.0.1:53");
+		let update = Update::Delete;
+		let mut msg = build_update(
+			0x1234,
+			"example.org",
+			"dkim._domainkey.example.org",
+			&update,
+		)
+		.unwrap();
+		sign_message(&cnf, &mut msg, None, 1681595158).unwrap();
+		assert_eq!(&msg[..4], &[0x12, 0x34, 0x28, 0x00]);
+		assert_eq!(&msg[4..12], &[0, 1, 0, 0, 0, 1, 0, 1]);
+		let (unsigned, mac) = split_tsig(&msg);
+		let mut expected = Hmac::<Sha256>::new_from_slice(SECRET).unwrap();
+		expected.update(&unsigned);
+		expected.update(b"\x0aupdate-key\x07example\x03org\x00\x00\xff\x00\x00\x00\x00");
+		expected.update(b"\x0bhmac-sha256\x00\x00\x00\x64\x3b\x1b\x16\x01\x2c\x00\x00\x00\x00");
+		expected.verify_slice(&mac).unwrap();
+	}
+
+	#[tokio::test]
+	async fn update_server() {
+		let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+		let cnf = get_config(&listener.local_addr().unwrap().to_string());
+		let server_cnf = cnf.clone();
+		let server = tokio::spawn(async move {
+			let mut queries = Vec::new();
+			for (rcode, soa) in [(RCODE_NXDOMAIN, Some("example.org")), (RCODE_NOERROR, None)] {
+				let (mut stream, _) = listener.accept().await.unwrap();
+				let len = stream.read_u16().await.unwrap() as usize;
+				let mut query = vec![0; len];
+				stream.read_exact(&mut query).await.unwrap();
+				let mut response = build_response(&query, rcode, soa);
+				let request_mac = get_request_mac(&query);
+				sign_message(&server_cnf, &mut response, Some(&request_mac), now()).unwrap();
+				stream.write_u16(response.len() as u16).await.unwrap();
+				stream.write_all(&response).await.unwrap();
+				queries.push(query);
+			}
+			queries
+		});
+		add_record(
+			&cnf,
+			"dkim._domainkey.mail.example.org",
+			"v=DKIM1; k=ed25519; p=test",
+		)
+		.await
+		.unwrap();
+		let queries = server.await.unwrap();
+		assert_eq!(&queries[0][2..4], &OPCODE_QUERY.to_be_bytes());
+		let zone = b"\x07example\x03org\x00\x00\x06\x00\x01";
+		assert_eq!(&queries[1][HEADER_LEN..HEADER_LEN + zone.len()], zone);
+		let txt = b"\x1av=DKIM1; k=ed25519; p=test";
+		assert!(queries[1].windows(txt.len()).any(|w| w == txt));
+	}
+
+	#[test]
+	fn signed_response() {
+		let cnf = get_config("127.0.0.1:53");
+		let mut query = build_soa_query(0x1234, "dkim._domainkey.example.org").unwrap();
+		let request_mac = sign_message(&cnf, &mut query, None, now()).unwrap();
+		let mut response = build_response(&query, RCODE_NOERROR, Some("example.org"));
+		sign_message(&cnf, &mut response, Some(&request_mac), now()).unwrap();
+		assert!(check_response(&cnf, 0x1234, &request_mac, &response).is_ok());
+		assert!(check_response(&cnf, 0x4321, &request_mac, &response).is_err());
+
+		let mut tampered = response.clone();
+		tampered[HEADER_LEN + 1] ^= 0x20;
+		let err = check_response(&cnf, 0x1234, &request_mac, &tampered).unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"invalid TSIG signature on the DNS response"
+		);
+
+		let other_cnf = Rfc2136Config::new("127.0.0.1:53", "update-key.example.org", vec![0; 32]);
+		let mut response = build_response(&query, RCODE_NOERROR, None);
+		sign_message(&other_cnf, &mut response, Some(&request_mac), now()).unwrap();
+		assert!(check_response(&cnf, 0x1234, &request_mac, &response).is_err());
+
+		let mut response = build_response(&query, RCODE_NOERROR, None);
+		sign_message(&cnf, &mut response, Some(&request_mac), now() - 3600).unwrap();
+		let err = check_response(&cnf, 0x1234, &request_mac, &response).unwrap_err();
+		assert_eq!(
+			err.to_string(),
+			"the DNS response has been signed at an invalid time"
+		);
+	}
+
+	#[test]
+	fn unsigned_response() {
+		let cnf = get_config("127.0.0.1:53");
+		let response = [0x12, 0x34, 0xa8, 0x05, 0, 0, 0, 0, 0, 0, 0, 0];
+		let err = check_response(&cnf, 0x1234, &[], &response).unwrap_err();
+		assert_eq!(err.to_string(), "unsigned DNS response: REFUSED");
+		assert!(check_response(&cnf, 0x4321, &[], &response).is_err());
+		let response = [0x12, 0x34, 0xa8, 0x00, 0, 0, 0, 0, 0, 0, 0, 0];
+		assert!(check_response(&cnf, 0x1234, &[], &response).is_err());
+	}
+
+	fn split_tsig(msg: &[u8]) -> (Vec<u8>, Vec<u8>) {
+		let key_name = b"\x0aupdate-key\x07example\x03org\x00";
+		let pos = msg
+			.windows(key_name.len())
+			.rposition(|w| w == key_name)
+			.unwrap();
+		let mut unsigned = msg[..pos].to_vec();
+		unsigned[11] -= 1;
+		let mac_pos = pos + key_name.len() + 10 + 13 + 6 + 2;
+		let mac_len = u16::from_be_bytes([msg[mac_pos], msg[mac_pos + 1]]) as usize;
+		let mac = msg[mac_pos + 2..mac_pos + 2 + mac_len].to_vec();
+		(unsigned, mac)
+	}
+}
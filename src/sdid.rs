@@ -0,0 +1,296 @@
+use crate::address::{parse_address_list, parse_mailbox, Mailbox};
+use crate::config::Config;
+use crate::parsed_message::ParsedMessage;
+use crate::session::Session;
+use anyhow::{anyhow, Result};
+use serde::{de, Deserialize, Deserializer};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SdidSource {
+	From,
+	Sender,
+	Envelope,
+}
+
+impl Default for SdidSource {
+	fn default() -> Self {
+		crate::DEFAULT_CNF_SDID_SOURCE
+	}
+}
+
+impl fmt::Display for SdidSource {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let s = match self {
+			Self::From => "from",
+			Self::Sender => "sender",
+			Self::Envelope => "envelope",
+		};
+		write!(f, "{s}")
+	}
+}
+
+impl FromStr for SdidSource {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"from" => Ok(Self::From),
+			"sender" => Ok(Self::Sender),
+			"envelope" => Ok(Self::Envelope),
+			_ => Err(format!("{s}: invalid SDID source")),
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for SdidSource {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		String::deserialize(deserializer)?
+			.parse()
+			.map_err(de::Error::custom)
+	}
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SubdomainPolicy {
+	#[default]
+	None,
+	Parent,
+	Own,
+}
+
+impl fmt::Display for SubdomainPolicy {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let s = match self {
+			Self::None => "none",
+			Self::Parent => "parent",
+			Self::Own => "own",
+		};
+		write!(f, "{s}")
+	}
+}
+
+impl FromStr for SubdomainPolicy {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"none" => Ok(Self::None),
+			"parent" => Ok(Self::Parent),
+			"own" => Ok(Self::Own),
+			_ => Err(format!("{s}: invalid subdomain policy")),
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for SubdomainPolicy {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		String::deserialize(deserializer)?
+			.parse()
+			.map_err(de::Error::custom)
+	}
+}
+
+/// Policy used to set the agent or user identifier (AUID) of the signature.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum AuidPolicy {
+	#[default]
+	None,
+	FromAddress,
+	FromDomain,
+	Fixed(String),
+}
+
+impl fmt::Display for AuidPolicy {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		match self {
+			Self::None => write!(f, "none"),
+			Self::FromAddress => write!(f, "from-address"),
+			Self::FromDomain => write!(f, "from-domain"),
+			Self::Fixed(auid) => write!(f, "{auid}"),
+		}
+	}
+}
+
+impl FromStr for AuidPolicy {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"none" => Ok(Self::None),
+			"from-address" => Ok(Self::FromAddress),
+			"from-domain" => Ok(Self::FromDomain),
+			_ => match s.rsplit_once('@') {
+				Some((_, domain)) if !domain.is_empty() => Ok(Self::Fixed(s.to_string())),
+				_ => Err(format!("{s}: invalid AUID policy")),
+			},
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for AuidPolicy {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		String::deserialize(deserializer)?
+			.parse()
+			.map_err(de::Error::custom)
+	}
+}
+
+impl SdidSource {
+	fn get_domain(&self, msg: &ParsedMessage<'_>, session: &Session) -> Result<String> {
+		let mailbox = match self {
+			Self::From => get_header_mailbox(msg, "from")?,
+			Self::Sender => get_header_mailbox(msg, "sender")?,
+			Self::Envelope => {
+				let address = session
+					.mail_from()
+					.filter(|a| !a.is_empty())
+					.ok_or(anyhow!("no envelope sender"))?;
+				parse_mailbox(address.as_bytes())?
+			}
+		};
+		Ok(mailbox.domain().to_lowercase())
+	}
+}
+
+pub fn get_sdid(
+	cnf: &Config,
+	msg: &ParsedMessage<'_>,
+	session: &Session,
+) -> Result<(String, SdidSource)> {
+	let mut errors = Vec::new();
+	for source in cnf.sdid_sources() {
+		match source.get_domain(msg, session) {
+			Ok(domain) => match cnf.signing_domain(&domain) {
+				Some(sdid) => return Ok((sdid, *source)),
+				None => errors.push(format!(
+					"{source}: {domain}: domain outside of the configured list"
+				)),
+			},
+			Err(err) => errors.push(format!("{source}: {err}")),
+		}
+	}
+	Err(anyhow!(
+		"unable to determine the SDID: {}",
+		errors.join(", ")
+	))
+}
+
+/// Returns the AUID to set in the signature, if any, according to the policy of the SDID.
+pub fn get_auid(cnf: &Config, msg: &ParsedMessage<'_>, sdid: &str) -> Result<Option<String>> {
+	let auid = match cnf.auid(sdid) {
+		AuidPolicy::None => return Ok(None),
+		AuidPolicy::FromAddress => get_header_mailbox(msg, "from")
+			.map_err(|e| anyhow!("from: {e}"))?
+			.to_string(),
+		AuidPolicy::FromDomain => format!(
+			"@{}",
+			get_header_mailbox(msg, "from")
+				.map_err(|e| anyhow!("from: {e}"))?
+				.domain()
+		),
+		AuidPolicy::Fixed(auid) => auid.to_string(),
+	};
+	check_auid(&auid, sdid)?;
+	Ok(Some(auid))
+}
+
+/// As per RFC 6376, section 3.5, the domain of the AUID must be the same as, or a subdomain of,
+/// the SDID.
+pub fn check_auid(auid: &str, sdid: &str) -> Result<()> {
+	let domain = auid
+		.rsplit_once('@')
+		.map(|(_, d)| d.to_lowercase())
+		.unwrap_or_default();
+	if domain == sdid || domain.ends_with(&format!(".{sdid}")) {
+		Ok(())
+	} else {
+		Err(anyhow!(
+			"{auid}: the AUID is neither in the {sdid} domain nor in one of its subdomains"
+		))
+	}
+}
+
+fn get_header_mailbox(msg: &ParsedMessage<'_>, header_name: &str) -> Result<Mailbox> {
+	let mut instances = crate::signature::get_header_instances(msg, header_name);
+	let header = instances.next().ok_or(anyhow!("header not found"))?;
+	if instances.next().is_some() {
+		return Err(anyhow!("several headers found"));
+	}
+	let mut lst = parse_address_list(header.value)?;
+	match lst.len() {
+		0 => Err(anyhow!("no address found")),
+		1 => Ok(lst.remove(0)),
+		_ => Err(anyhow!(
+			"several addresses found: {}",
+			lst.iter()
+				.map(|mb| mb.to_string())
+				.collect::<Vec<String>>()
+				.join(", ")
+		)),
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn auid() {
+		assert!(check_auid("@example.org", "example.org").is_ok());
+		assert!(check_auid("john@news.Example.org", "example.org").is_ok());
+		assert!(check_auid("john@badexample.org", "example.org").is_err());
+		assert!(check_auid("john@example.org", "news.example.org").is_err());
+		assert_eq!(
+			"@news.example.org".parse::<AuidPolicy>().unwrap(),
+			AuidPolicy::Fixed("@news.example.org".into())
+		);
+		assert_eq!(
+			"From-Address".parse::<AuidPolicy>().unwrap(),
+			AuidPolicy::FromAddress
+		);
+		assert!("example.org".parse::<AuidPolicy>().is_err());
+		assert!("john@".parse::<AuidPolicy>().is_err());
+	}
+
+	#[test]
+	fn sources() {
+		let raw =
+			b"From: John <john@Example.org>\r\nSender: list@lists.example.org\r\n\r\nbody\r\n";
+		let msg = ParsedMessage::from_bytes(raw).unwrap();
+		let session = Session::default();
+		assert_eq!(
+			SdidSource::From.get_domain(&msg, &session).unwrap(),
+			"example.org"
+		);
+		assert_eq!(
+			SdidSource::Sender.get_domain(&msg, &session).unwrap(),
+			"lists.example.org"
+		);
+		assert!(SdidSource::Envelope.get_domain(&msg, &session).is_err());
+	}
+
+	#[test]
+	fn several_from() {
+		let raw = b"From: a@example.org, b@example.org\r\n\r\nbody\r\n";
+		let msg = ParsedMessage::from_bytes(raw).unwrap();
+		let err = SdidSource::From
+			.get_domain(&msg, &Session::default())
+			.unwrap_err()
+			.to_string();
+		assert_eq!(err, "several addresses found: a@example.org, b@example.org");
+		let err = SdidSource::Sender
+			.get_domain(&msg, &Session::default())
+			.unwrap_err()
+			.to_string();
+		assert_eq!(err, "header not found");
+		let raw = b"From: a@example.org\r\nFrom: b@example.org\r\n\r\nbody\r\n";
+		let msg = ParsedMessage::from_bytes(raw).unwrap();
+		let err = SdidSource::From
+			.get_domain(&msg, &Session::default())
+			.unwrap_err()
+			.to_string();
+		assert_eq!(err, "several headers found");
+	}
+}
@@ -0,0 +1,252 @@
+use crate::report::{Report, ReportEvent};
+use serde::{de, Deserialize, Deserializer};
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+pub const DEFAULT_LOCAL_NETWORKS: &[&str] = &["127.0.0.0/8", "::1/128"];
+const LOCAL_SOCKET_PREFIX: &str = "unix:";
+
+#[derive(Clone, Debug, Default)]
+pub struct Session {
+	local: bool,
+	authenticated: bool,
+	mail_from: Option<String>,
+}
+
+impl Session {
+	pub fn new(src: &str, local_networks: &[IpNetwork]) -> Self {
+		let local = match parse_src_addr(src) {
+			Some(addr) => local_networks.iter().any(|n| n.contains(&addr)),
+			None => src == "local" || src.starts_with(LOCAL_SOCKET_PREFIX),
+		};
+		Self {
+			local,
+			..Default::default()
+		}
+	}
+
+	pub fn update(&mut self, report: &Report) {
+		match report.get_event() {
+			ReportEvent::LinkAuth { success } => {
+				self.authenticated = *success;
+			}
+			ReportEvent::TxMailFrom { address, success } => {
+				self.mail_from = success.then(|| address.to_string());
+			}
+			ReportEvent::LinkConnect { .. } | ReportEvent::LinkDisconnect => {}
+		}
+	}
+
+	pub fn is_local(&self) -> bool {
+		self.local
+	}
+
+	pub fn is_authenticated(&self) -> bool {
+		self.authenticated
+	}
+
+	pub fn mail_from(&self) -> Option<&str> {
+		self.mail_from.as_deref()
+	}
+}
+
+fn parse_src_addr(src: &str) -> Option<IpAddr> {
+	if let Ok(addr) = src.parse() {
+		return Some(addr);
+	}
+	let addr = match src.rsplit_once(':') {
+		Some((addr, port)) if port.bytes().all(|c| c.is_ascii_digit()) => addr,
+		_ => src,
+	};
+	let addr = addr.trim_start_matches('[').trim_end_matches(']');
+	let addr = addr.strip_prefix("IPv6:").unwrap_or(addr);
+	addr.parse().ok()
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum SignPolicy {
+	#[default]
+	Any,
+	Auth,
+	Local,
+	AuthOrLocal,
+}
+
+impl SignPolicy {
+	pub fn allows(&self, session: &Session) -> bool {
+		match self {
+			Self::Any => true,
+			Self::Auth => session.is_authenticated(),
+			Self::Local => session.is_local(),
+			Self::AuthOrLocal => session.is_authenticated() || session.is_local(),
+		}
+	}
+}
+
+impl fmt::Display for SignPolicy {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		let s = match self {
+			Self::Any => "any",
+			Self::Auth => "auth",
+			Self::Local => "local",
+			Self::AuthOrLocal => "auth-or-local",
+		};
+		write!(f, "{s}")
+	}
+}
+
+impl FromStr for SignPolicy {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		match s.to_lowercase().as_str() {
+			"any" => Ok(Self::Any),
+			"auth" => Ok(Self::Auth),
+			"local" => Ok(Self::Local),
+			"auth-or-local" => Ok(Self::AuthOrLocal),
+			_ => Err(format!("{s}: invalid signing policy")),
+		}
+	}
+}
+
+impl<'de> Deserialize<'de> for SignPolicy {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		String::deserialize(deserializer)?
+			.parse()
+			.map_err(de::Error::custom)
+	}
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct IpNetwork {
+	addr: IpAddr,
+	prefix_len: u8,
+}
+
+impl IpNetwork {
+	pub fn contains(&self, addr: &IpAddr) -> bool {
+		let addr = match addr {
+			IpAddr::V6(a) => a.to_ipv4_mapped().map(IpAddr::V4).unwrap_or(*addr),
+			IpAddr::V4(_) => *addr,
+		};
+		match (self.addr, addr) {
+			(IpAddr::V4(net), IpAddr::V4(a)) => {
+				let mask = u32::MAX
+					.checked_shl(32 - self.prefix_len as u32)
+					.unwrap_or(0);
+				u32::from(net) & mask == u32::from(a) & mask
+			}
+			(IpAddr::V6(net), IpAddr::V6(a)) => {
+				let mask = u128::MAX
+					.checked_shl(128 - self.prefix_len as u32)
+					.unwrap_or(0);
+				u128::from(net) & mask == u128::from(a) & mask
+			}
+			_ => false,
+		}
+	}
+}
+
+impl fmt::Display for IpNetwork {
+	fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+		write!(f, "{}/{}", self.addr, self.prefix_len)
+	}
+}
+
+impl FromStr for IpNetwork {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let (addr, prefix_len) = match s.split_once('/') {
+			Some((addr, len)) => (addr, Some(len)),
+			None => (s, None),
+		};
+		let addr: IpAddr = addr
+			.parse()
+			.map_err(|_| format!("{s}: invalid IP address"))?;
+		let max_len = if addr.is_ipv4() { 32 } else { 128 };
+		let prefix_len = match prefix_len {
+			Some(len) => len
+				.parse()
+				.ok()
+				.filter(|l| *l <= max_len)
+				.ok_or(format!("{s}: invalid prefix length"))?,
+			None => max_len,
+		};
+		Ok(Self { addr, prefix_len })
+	}
+}
+
+impl<'de> Deserialize<'de> for IpNetwork {
+	fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+		String::deserialize(deserializer)?
+			.parse()
+			.map_err(de::Error::custom)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn default_networks() -> Vec<IpNetwork> {
+		DEFAULT_LOCAL_NETWORKS
+			.iter()
+			.map(|n| n.parse().unwrap())
+			.collect()
+	}
+
+	#[test]
+	fn network_contains() {
+		let net: IpNetwork = "192.0.2.0/24".parse().unwrap();
+		assert!(net.contains(&"192.0.2.42".parse().unwrap()));
+		assert!(net.contains(&"::ffff:192.0.2.42".parse().unwrap()));
+		assert!(!net.contains(&"192.0.3.42".parse().unwrap()));
+		assert!(!net.contains(&"2001:db8::1".parse().unwrap()));
+		let net: IpNetwork = "2001:db8::/32".parse().unwrap();
+		assert!(net.contains(&"2001:db8::1".parse().unwrap()));
+		assert!(!net.contains(&"2001:db9::1".parse().unwrap()));
+		let net: IpNetwork = "0.0.0.0/0".parse().unwrap();
+		assert!(net.contains(&"203.0.113.1".parse().unwrap()));
+		let net: IpNetwork = "192.0.2.1".parse().unwrap();
+		assert!(net.contains(&"192.0.2.1".parse().unwrap()));
+		assert!(!net.contains(&"192.0.2.2".parse().unwrap()));
+	}
+
+	#[test]
+	fn invalid_network() {
+		assert!("192.0.2.0/33".parse::<IpNetwork>().is_err());
+		assert!("2001:db8::/129".parse::<IpNetwork>().is_err());
+		assert!("example.org/24".parse::<IpNetwork>().is_err());
+	}
+
+	#[test]
+	fn local_session() {
+		let networks = default_networks();
+		assert!(Session::new("127.0.0.1:33174", &networks).is_local());
+		assert!(Session::new("[::1]:33174", &networks).is_local());
+		assert!(Session::new("unix:/var/run/smtpd.sock", &networks).is_local());
+		assert!(Session::new("local", &networks).is_local());
+		assert!(!Session::new("199.185.178.25:33174", &networks).is_local());
+		assert!(!Session::new("[2001:db8::1]:33174", &networks).is_local());
+	}
+
+	#[test]
+	fn policy() {
+		let networks = default_networks();
+		let mut session = Session::new("199.185.178.25:33174", &networks);
+		assert!(SignPolicy::Any.allows(&session));
+		assert!(!SignPolicy::Auth.allows(&session));
+		assert!(!SignPolicy::Local.allows(&session));
+		assert!(!SignPolicy::AuthOrLocal.allows(&session));
+		let report = Report::from_bytes(
+			b"report|0.7|1576146008.006099|smtp-in|link-auth|7641df9771b4ed00|pass|user\n",
+		)
+		.unwrap();
+		session.update(&report);
+		assert!(SignPolicy::Auth.allows(&session));
+		assert!(!SignPolicy::Local.allows(&session));
+		assert!(SignPolicy::AuthOrLocal.allows(&session));
+	}
+}
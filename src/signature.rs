@@ -1,46 +1,163 @@
-use crate::algorithm::Algorithm;
-use crate::canonicalization::Canonicalization;
+use crate::algorithm::{Algorithm, HashAlgorithm};
+use crate::canonicalization::{BodyCanonicalizer, Canonicalization, CanonicalizationType};
 use crate::config::Config;
 use crate::parsed_message::{ParsedHeader, ParsedMessage};
 use anyhow::{anyhow, Result};
 use base64::{engine::general_purpose, Engine as _};
+use sha1::Sha1;
+use sha2::digest::Update;
 use sha2::{Digest, Sha256};
 use sqlx::types::time::OffsetDateTime;
 use sqlx::SqlitePool;
+use std::collections::{HashMap, HashSet};
 use tokio::time::{sleep, Duration};
 
+/// Computes the body hash as the body lines are received. The body is canonicalized once, using
+/// the body canonicalization of the signing domain, and hashed using each hash algorithm of its
+/// signing algorithms.
+#[derive(Clone, Debug)]
+pub struct BodyHasher {
+	canonicalization: CanonicalizationType,
+	canonicalizer: BodyCanonicalizer,
+	hashers: BodyHashers,
+}
+
+impl BodyHasher {
+	pub fn new(canonicalization: CanonicalizationType, hash_algorithms: &[HashAlgorithm]) -> Self {
+		Self {
+			canonicalization,
+			canonicalizer: BodyCanonicalizer::new(canonicalization),
+			hashers: BodyHashers {
+				sha1: hash_algorithms
+					.contains(&HashAlgorithm::Sha1)
+					.then(CountingHasher::default),
+				sha256: hash_algorithms
+					.contains(&HashAlgorithm::Sha256)
+					.then(CountingHasher::default),
+			},
+		}
+	}
+
+	pub fn update(&mut self, line: &[u8]) {
+		self.canonicalizer.process_line(line, &mut self.hashers);
+	}
+
+	/// Returns the body hash and the length of the canonicalized body.
+	pub fn body_hash(
+		&self,
+		canonicalization: Canonicalization,
+		hash_algorithm: HashAlgorithm,
+	) -> Result<(Vec<u8>, usize)> {
+		if canonicalization.body_alg() != self.canonicalization {
+			return Err(anyhow!(
+				"the body has not been canonicalized using {}",
+				canonicalization.body_alg()
+			));
+		}
+		let ret = match hash_algorithm {
+			HashAlgorithm::Sha1 => self.hashers.sha1.as_ref().map(|h| self.finish(h)),
+			HashAlgorithm::Sha256 => self.hashers.sha256.as_ref().map(|h| self.finish(h)),
+		};
+		ret.ok_or(anyhow!(
+			"the body has not been hashed using {hash_algorithm}"
+		))
+	}
+
+	fn finish<H: Clone + Digest + Update>(&self, hasher: &CountingHasher<H>) -> (Vec<u8>, usize) {
+		let mut canonicalizer = self.canonicalizer.clone();
+		let mut hasher = hasher.clone();
+		canonicalizer.finish(&mut hasher);
+		(Digest::finalize(hasher.hasher).to_vec(), hasher.length)
+	}
+}
+
+#[derive(Clone, Debug)]
+struct BodyHashers {
+	sha1: Option<CountingHasher<Sha1>>,
+	sha256: Option<CountingHasher<Sha256>>,
+}
+
+impl Update for BodyHashers {
+	fn update(&mut self, data: &[u8]) {
+		if let Some(hasher) = &mut self.sha1 {
+			hasher.update(data);
+		}
+		if let Some(hasher) = &mut self.sha256 {
+			hasher.update(data);
+		}
+	}
+}
+
+/// Keeps track of the length of the canonicalized body, as required by the l= tag.
+#[derive(Clone, Debug, Default)]
+struct CountingHasher<H> {
+	hasher: H,
+	length: usize,
+}
+
+impl<H: Update> Update for CountingHasher<H> {
+	fn update(&mut self, data: &[u8]) {
+		self.length += data.len();
+		self.hasher.update(data);
+	}
+}
+
 pub struct Signature {
 	algorithm: Algorithm,
 	canonicalization: Canonicalization,
 	selector: String,
 	sdid: String,
+	auid: Option<String>,
 	timestamp: i64,
 	expiration: Option<u64>,
 	headers: Vec<String>,
+	body_length: Option<usize>,
 	body_hash: Vec<u8>,
+	copied_headers: Option<Vec<(String, Vec<u8>)>>,
 	signature: Vec<u8>,
+	folding_width: usize,
 }
 
 impl Signature {
-	pub async fn new(db: &SqlitePool, cnf: &Config, msg: &ParsedMessage<'_>) -> Result<Self> {
-		let algorithm = cnf.algorithm();
-		let sdid = get_sdid(cnf, msg)?;
-		let (selector, signing_key) = get_db_data(db, &sdid, algorithm).await?;
+	pub async fn new(
+		db: &SqlitePool,
+		cnf: &Config,
+		msg: &ParsedMessage<'_>,
+		body_hasher: &BodyHasher,
+		sdid: &str,
+		auid: Option<&str>,
+		algorithm: Algorithm,
+	) -> Result<Self> {
+		let (selector, signing_key) = get_db_data(db, sdid, algorithm).await?;
 		let timestamp = OffsetDateTime::now_utc().unix_timestamp();
-		let expiration = cnf.expiration().map(|x| x + timestamp as u64);
+		let expiration = cnf.expiration(sdid).map(|x| x + timestamp as u64);
+		let canonicalization = cnf.canonicalization(sdid);
+		let (body_hash, body_length) =
+			body_hasher.body_hash(canonicalization, algorithm.hash_algorithm())?;
+		let body_length = cnf.body_length(sdid).then_some(body_length);
+		let headers = get_headers(cnf, sdid, msg);
+		let copied_headers = cnf
+			.copy_headers(sdid)
+			.then(|| get_copied_headers(&headers, msg));
 		let mut sig = Self {
 			algorithm,
-			canonicalization: cnf.canonicalization(),
+			canonicalization,
 			selector,
-			sdid,
+			sdid: sdid.to_string(),
+			auid: auid.map(|a| a.to_string()),
 			timestamp,
 			expiration,
-			headers: get_headers(cnf, msg),
-			body_hash: Vec::new(),
+			headers,
+			body_length,
+			body_hash,
+			copied_headers,
 			signature: Vec::new(),
+			folding_width: cnf.folding_width(),
+		};
+		let header_hash = match algorithm.hash_algorithm() {
+			HashAlgorithm::Sha1 => sig.compute_header_hash::<Sha1>(msg),
+			HashAlgorithm::Sha256 => sig.compute_header_hash::<Sha256>(msg),
 		};
-		sig.compute_body_hash::<Sha256>(msg);
-		let header_hash = sig.compute_header_hash::<Sha256>(msg);
 		sig.signature = algorithm.sign(&signing_key, &header_hash)?;
 		Ok(sig)
 	}
@@ -49,102 +166,196 @@ impl Signature {
 		let expiration = self
 			.expiration
 			.map(|x| format!(" x={x};"))
-			.unwrap_or(String::new());
+			.unwrap_or_default();
+		let auid = self
+			.auid
+			.as_ref()
+			.map(|x| format!("\r\n\ti={};", crate::quoted_printable::encode(x.as_bytes())))
+			.unwrap_or_default();
+		let body_length = self
+			.body_length
+			.map(|x| format!("\r\n\tl={x};"))
+			.unwrap_or_default();
+		let headers = self
+			.headers
+			.iter()
+			.enumerate()
+			.map(|(i, h)| {
+				if i == self.headers.len() - 1 {
+					h.to_string()
+				} else {
+					format!("{h}:")
+				}
+			})
+			.collect::<Vec<_>>();
+		let copied_headers = self
+			.copied_headers
+			.as_ref()
+			.map(|x| format!("{};", self.fold_tag("z", &get_copied_headers_atoms(x))))
+			.unwrap_or_default();
 		format!(
-			"DKIM-Signature: v=1; a={algorithm}; k={key_type}; c={canonicalization};\r\n\tt={timestamp};{expiration}\r\n\td={sdid};\r\n\ts={selector};\r\n\th={headers};\r\n\tbh={body_hash};\r\n\tb={signature}",
+			"DKIM-Signature: v=1; a={algorithm}; k={key_type}; c={canonicalization};\r\n\tt={timestamp};{expiration}\r\n\td={sdid};{auid}\r\n\ts={selector};{headers};{body_length}{copied_headers}{body_hash};{signature}",
 			algorithm=self.algorithm.display(),
 			key_type=self.algorithm.key_type(),
-			canonicalization=self.canonicalization.to_string(),
+			canonicalization=self.canonicalization,
 			selector=self.selector,
 			sdid=self.sdid,
+			auid=auid,
 			timestamp=self.timestamp,
 			expiration=expiration,
-			headers=self.headers.join(":"),
-			body_hash=general_purpose::STANDARD.encode(&self.body_hash),
-			signature=general_purpose::STANDARD.encode(&self.signature),
+			headers=self.fold_tag("h", &headers),
+			body_length=body_length,
+			copied_headers=copied_headers,
+			body_hash=self.fold_tag("bh", &get_base64_atoms(&self.body_hash)),
+			signature=self.fold_tag("b", &get_base64_atoms(&self.signature)),
 		)
 	}
 
-	fn compute_body_hash<H: Digest>(&mut self, msg: &ParsedMessage<'_>) {
-		let mut hasher = H::new();
-		let body = self.canonicalization.process_body(msg.body);
-		hasher.update(&body);
-		self.body_hash = hasher.finalize().to_vec();
+	/// Writes a tag, without its separator, on a new line and folds its value so lines do not
+	/// exceed the folding width.
+	/// Since the folding of the b= tag only occurs after its first character, the header is
+	/// identical once the b= value has been removed for the computation of the signature.
+	fn fold_tag(&self, name: &str, atoms: &[String]) -> String {
+		let mut ret = format!("\r\n\t{name}=");
+		let mut line_len = name.len() + 2;
+		for (i, atom) in atoms.iter().enumerate() {
+			// Room is kept for the tag separator after the last atom.
+			let atom_len = if i == atoms.len() - 1 {
+				atom.len() + 1
+			} else {
+				atom.len()
+			};
+			if i != 0 && line_len + atom_len > self.folding_width {
+				ret += "\r\n\t ";
+				line_len = 2;
+			}
+			ret += atom;
+			line_len += atom.len();
+		}
+		ret
 	}
 
-	fn compute_header_hash<H: Digest>(&mut self, msg: &ParsedMessage<'_>) -> Vec<u8> {
+	fn compute_header_hash<H: Digest + Update>(&mut self, msg: &ParsedMessage<'_>) -> Vec<u8> {
 		let mut hasher = H::new();
-		for header_name in &self.headers {
-			if let Some(raw_header) = get_header(msg, header_name) {
-				let header = self.canonicalization.process_header(raw_header.raw);
-				hasher.update(&header);
-			}
+		for header in get_signed_headers(&self.headers, msg) {
+			self.canonicalization.write_header(header.raw, &mut hasher);
 		}
 		let dkim_header = format!("{}\r\n", self.get_header());
 		let mut dkim_header = self.canonicalization.process_header(dkim_header.as_bytes());
 		dkim_header.pop();
 		dkim_header.pop();
-		hasher.update(dkim_header);
+		Update::update(&mut hasher, &dkim_header);
 		hasher.finalize().to_vec()
 	}
 }
 
-fn get_sdid(cnf: &Config, msg: &ParsedMessage<'_>) -> Result<String> {
-	if let Some(header) = get_header(msg, "from") {
-		if let Some(arb_pos) = header.value.iter().rposition(|&c| c == b'@') {
-			let name = &header.value[arb_pos + 1..];
-			let end_pos = name
-				.iter()
-				.position(|&c| c == b'>')
-				.unwrap_or(name.len() - 2);
-			if let Ok(sdid) = String::from_utf8(name[..end_pos].to_vec()) {
-				if cnf.domains().contains(&sdid) {
-					return Ok(sdid);
-				} else {
-					return Err(anyhow!(
-						"unable to sign for a domain outside of the configured list: {sdid}"
-					));
-				}
-			}
+fn get_headers(cnf: &Config, sdid: &str, msg: &ParsedMessage<'_>) -> Vec<String> {
+	let oversign = cnf.headers_oversign(sdid);
+	let names = cnf
+		.headers(sdid)
+		.iter()
+		.map(|h| (h, true))
+		.chain(cnf.headers_optional(sdid).iter().map(|h| (h, false)))
+		.chain(oversign.iter().map(|h| (h, true)));
+	let mut seen = HashSet::new();
+	let mut lst = Vec::with_capacity(64);
+	for (header_name, required) in names {
+		if !seen.insert(header_name) {
+			continue;
 		}
+		let nb_instances = get_header_instances(msg, header_name).count();
+		// Every instance is signed. Over-signed headers are listed once more than they occur so
+		// any added instance invalidates the signature.
+		let nb = if oversign.contains(header_name) {
+			nb_instances + 1
+		} else if nb_instances == 0 && required {
+			1
+		} else {
+			nb_instances
+		};
+		let name = get_header_name(msg, header_name).unwrap_or_else(|| header_name.to_string());
+		lst.extend(std::iter::repeat_n(name, nb));
 	}
-	Err(anyhow!("unable to determine the SDID"))
+	lst.sort();
+	lst
 }
 
-fn get_headers(cnf: &Config, msg: &ParsedMessage<'_>) -> Vec<String> {
-	let nb_headers = cnf.headers().len() + cnf.headers_optional().len();
-	let mut lst = Vec::with_capacity(nb_headers);
-	for header_name in cnf.headers() {
-		if let Some(name) = get_header_name(msg, header_name) {
-			lst.push(name);
-		} else {
-			lst.push(header_name.to_string());
+// RFC 6376, section 5.4.2: instances of a repeated header are used from the bottom up.
+fn get_signed_headers<'a>(
+	headers: &[String],
+	msg: &'a ParsedMessage<'a>,
+) -> Vec<&'a ParsedHeader<'a>> {
+	let mut nb_used: HashMap<String, usize> = HashMap::with_capacity(headers.len());
+	let mut ret = Vec::with_capacity(headers.len());
+	for header_name in headers {
+		let header_name = header_name.to_lowercase();
+		let index = nb_used.entry(header_name.clone()).or_default();
+		if let Some(header) = get_header_instances(msg, &header_name).rev().nth(*index) {
+			ret.push(header);
 		}
+		*index += 1;
 	}
-	for header_name in cnf.headers_optional() {
-		if let Some(name) = get_header_name(msg, header_name) {
-			lst.push(name);
+	ret
+}
+
+fn get_copied_headers(headers: &[String], msg: &ParsedMessage<'_>) -> Vec<(String, Vec<u8>)> {
+	get_signed_headers(headers, msg)
+		.into_iter()
+		.map(|header| {
+			let name = String::from_utf8_lossy(header.name).to_string();
+			let value = header.value.strip_suffix(b"\r\n").unwrap_or(header.value);
+			let start = value
+				.iter()
+				.position(|&c| c != b' ' && c != b'\t')
+				.unwrap_or(value.len());
+			(name, value[start..].to_vec())
+		})
+		.collect()
+}
+
+// RFC 6376, section 3.5: folding whitespace may be inserted after a vertical bar and inside the
+// quoted-printable values, but not inside the header names nor the encoded characters.
+fn get_copied_headers_atoms(copied_headers: &[(String, Vec<u8>)]) -> Vec<String> {
+	let mut atoms: Vec<String> = Vec::new();
+	for (name, value) in copied_headers {
+		if let Some(last) = atoms.last_mut() {
+			last.push('|');
+		}
+		atoms.push(format!("{name}:"));
+		let value = crate::quoted_printable::encode_copied_header(value);
+		let mut i = 0;
+		while i < value.len() {
+			let atom_len = if value.as_bytes()[i] == b'=' { 3 } else { 1 };
+			atoms.push(value[i..i + atom_len].to_string());
+			i += atom_len;
 		}
 	}
-	lst.sort();
-	lst
+	atoms
+}
+
+// RFC 6376, section 3.5: folding whitespace may be inserted anywhere in the b= and bh= values.
+fn get_base64_atoms(data: &[u8]) -> Vec<String> {
+	general_purpose::STANDARD
+		.encode(data)
+		.chars()
+		.map(|c| c.to_string())
+		.collect()
 }
 
 fn get_header_name(msg: &ParsedMessage<'_>, header_name: &str) -> Option<String> {
-	match get_header(msg, header_name) {
-		Some(header) => String::from_utf8(header.name.to_vec()).ok(),
-		None => None,
-	}
+	get_header_instances(msg, header_name)
+		.next()
+		.and_then(|header| String::from_utf8(header.name.to_vec()).ok())
 }
 
-fn get_header<'a>(
+pub fn get_header_instances<'a>(
 	msg: &'a ParsedMessage<'a>,
-	header_name: &'a str,
-) -> Option<&'a ParsedHeader<'a>> {
+	header_name: &str,
+) -> impl DoubleEndedIterator<Item = &'a ParsedHeader<'a>> {
 	let header_name = header_name.to_lowercase();
 	msg.headers
 		.iter()
-		.find(|&header| header.name_lower == header_name)
+		.filter(move |&header| header.name_lower == header_name)
 }
 
 async fn get_db_data(
@@ -154,13 +365,39 @@ async fn get_db_data(
 ) -> Result<(String, String)> {
 	let mut ctn = 0;
 	loop {
-		let res: Option<(String, String)> = sqlx::query_as(crate::db::SELECT_LATEST_SIGNING_KEY)
+		let res: Option<(String, String, i64)> =
+			sqlx::query_as(crate::db::SELECT_LATEST_SIGNING_KEY)
+				.bind(sdid)
+				.bind(algorithm.to_string())
+				.fetch_optional(db)
+				.await?;
+		if let Some((selector, private_key, not_after)) = res {
+			// Until it is revoked, an expired key is still better than no signature.
+			if not_after <= OffsetDateTime::now_utc().unix_timestamp() {
+				log::warn!("{selector}._domainkey.{sdid}: the {algorithm} key is past the end of its cryptoperiod, the key rotation has failed");
+			}
+			return Ok((selector, private_key));
+		}
+		// Waiting for the publication delay would hold the message for hours, so the retries are
+		// only meant for a key which is being generated.
+		let pending: Option<(bool, i64)> = sqlx::query_as(crate::db::SELECT_PENDING_SIGNING_KEY)
 			.bind(sdid)
 			.bind(algorithm.to_string())
 			.fetch_optional(db)
 			.await?;
-		if let Some((selector, private_key)) = res {
-			return Ok((selector, private_key));
+		match pending {
+			Some((true, usable_after)) => {
+				let usable_after = OffsetDateTime::from_unix_timestamp(usable_after)?;
+				return Err(anyhow!(
+					"{sdid}: no usable {algorithm} key, the pending key will not be used before {usable_after}"
+				));
+			}
+			Some((false, _)) => {
+				return Err(anyhow!(
+					"{sdid}: no usable {algorithm} key, the DNS record of the pending key has not been published yet"
+				));
+			}
+			None => {}
 		}
 		if ctn == crate::SIG_RETRY_NB_RETRY {
 			return Err(anyhow!("unable to retrieve key material"));
@@ -177,6 +414,7 @@ mod tests {
 	const KEY_ED25519: &str = "Av46g0s6+qCczlLeIkSmD/yD7GX5pDjl8SVTSeVZIhc=";
 	const KEY_RSA2048: &str = "MIIEvgIBADANBgkqhkiG9w0BAQEFAASCBKgwggSkAgEAAoIBAQDDYGEHGABdLxLbvqiuaGdYV9ndsFzdO31AaZoSfCVF0TVPeJ5B/YWyUzc6YIr7qFyfA4tiRPu3/Fy2vzYzG2lcYBkSzJsQWkiOWv6C3CpxYX2tBrAKnJx3ADyr9P3whztyKQBbSNLy+CCNJQ5967Z+PCOeiFAX4XvfCFMpwxsUo6Pv2SYi9PwOq8HwzUQHyy39zOs68XIUF85DCXiQ1kztXzu8HX8nuLX7AnLrf0ZiFGrbSbUjj/F7XVPh2QJxcBLZjrnpve73nrrYsIVnbCvfV563CuFq0PvsUofW7Ckbpwdx26AD59ssaOBNp/uK04xqPSQZyADEngcfkOBdqeHlAgMBAAECggEAYqZQd6d7z5FMQV5Uh7O5staw8Anz6dT22kY4AGtk2orXEyiBKCrdjfwMn9JNeuI42iWNBHJ2cyDeo3uK32VQ3s66+k4LYcdkaVSyu9p1J8ilD2+YBdOsAT5CZEUQz5lIv8zKHE16DiqRLRNv7M7O15CAH7UOU/CLfkMS0rxr+Q//dw94mUTXSy+XKwWgdQSxjiqcfEFEArtP4QH+BM2j7Jk+cMm4OlOklOLlcktSgrCSGp4uqt9BYTq86XFSVaZHbirKAheO7mv9xSXj46zenWaSwWDJ/zYSQHUzTwdCcm35gHeGvUvBo/njCzfvNM6xWl+vrMzD9i4pB0PZ6yi0IQKBgQD/97QWjwdCt7ubRv453lLZ2CXDNt/QFKOr+ESlDPkCxmb/DIY8HDqbJpdyPrCm6MHL8lUBLNixSUfkrCFFaGgpHyG6E5qJT0/UVVwSWUAWciOY7SOZXMPgvkahU39GzPDE8m+mihyDPT98vUqX4HTZJzannaSESGeVtADYXN416QKBgQDDZrYuD4bzjft7XNmmbdl52sfCcKgduzmooSMjeIKffSZuCvpVBA0kEgqLalLaMSCjcx95djufqDkU+RBT7lAbZDYd+lD4m4dNzGqy1hgTVfBaPOYCg4iC2WKiHZDIw8n630gFyRZDAn+KINbcSn7V2ZxqDa1ZE6wAWgiBn98CnQKBgQDSmFnytXqjycbw2lgQBHrmAJARLPS3nkOLGZhgs2usfNAAx60ph5AwVnAD7tAogxfvVFHbxaoDMueTnItDL8ODEboN/lMG5dooOJKoBgZUcVQYXgMMCuad4e76jFgLSFJPt6dkvfz3fUzetF7K1kFM6JZvEaRpsaiH4rFPUhkBAQKBgHwXepL94WJDRPYvHToIgRhVzI67JMjc4d0pmDsqiSnoPMOdzSS4ke/aVT/8oelXUbb7oX1tjKf0GWwsUCY9Ljp3Bbc8BLgdbWwG6avxMxD0ftOP4TKvfb47d9wkkpItZNQhgIfMEIs1xvFdsZXs6We97wua6/+p8o22n7hSYzoxAoGBAM9zQgkeV2U++yi4memcNX1sLnRHaBUSShj+IXhOM8Hpw5deyxFKUh2sD537CJxKOx+8XMKvWilY2MFRCZIlTAQBasEfj9+YZSLY7kFHfWKcUKhqVQ+5M+LgbuZjf6X/0Y+2Lqc585NoxmMDc4GC/1t4eagXqMvcuh10S2JP9RWp";
 	const MSG_01_RAW: &[u8] = include_bytes!("../tests/test_01.msg");
+	const MSG_02_RAW: &[u8] = include_bytes!("../tests/test_02.msg");
 	const MSG_01_HEADERS: &[&str] = &[
 		"Date",
 		"From",
@@ -187,22 +425,41 @@ mod tests {
 		"reply-to",
 	];
 
+	fn body_hash(raw_msg: &[u8], canonicalization: Canonicalization) -> Vec<u8> {
+		let mut body_hasher =
+			BodyHasher::new(canonicalization.body_alg(), &[HashAlgorithm::Sha256]);
+		let body_index = raw_msg.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+		let body = &raw_msg[body_index + 4..];
+		let body = body.strip_suffix(b"\n").unwrap_or(body);
+		for line in body.split(|&c| c == b'\n') {
+			body_hasher.update(line);
+		}
+		body_hasher
+			.body_hash(canonicalization, HashAlgorithm::Sha256)
+			.unwrap()
+			.0
+	}
+
 	#[test]
 	fn test_simple_simple() {
-		let ref_sig_header = "DKIM-Signature: v=1; a=ed25519-sha256; k=ed25519; c=simple/simple;\r\n\tt=1681595158;\r\n\td=example.org;\r\n\ts=dkim-b3fb546a27bb44dd88a1fd2b4b3e2e96;\r\n\th=Date:From:Resent-Date:Subject:To:cc:reply-to;\r\n\tbh=z85OKVJZHnmg3qFlSpLbpPCZ00irfBdrzQUtabiSl3A=;\r\n\tb=YGbtIaodjitU3jNtwRA2+AJ/i5W4VxmZuESKnv08ofZ7nuYLUiqaS1sR3DGeJ1t83R5ZmPSJAQPBYRg3usJxBg==";
+		let ref_sig_header = "DKIM-Signature: v=1; a=ed25519-sha256; k=ed25519; c=simple/simple;\r\n\tt=1681595158;\r\n\td=example.org;\r\n\ts=dkim-b3fb546a27bb44dd88a1fd2b4b3e2e96;\r\n\th=Date:From:Resent-Date:Subject:To:cc:reply-to;\r\n\tbh=z85OKVJZHnmg3qFlSpLbpPCZ00irfBdrzQUtabiSl3A=;\r\n\tb=YGbtIaodjitU3jNtwRA2+AJ/i5W4VxmZuESKnv08ofZ7nuYLUiqaS1sR3DGeJ1t83R5ZmPSJAQP\r\n\t BYRg3usJxBg==";
 		let msg = ParsedMessage::from_bytes(MSG_01_RAW).unwrap();
 		let mut sig = Signature {
 			algorithm: Algorithm::Ed25519Sha256,
 			canonicalization: "simple/simple".parse().unwrap(),
 			selector: "dkim-b3fb546a27bb44dd88a1fd2b4b3e2e96".into(),
 			sdid: "example.org".into(),
+			auid: None,
 			timestamp: 1681595158,
 			expiration: None,
 			headers: MSG_01_HEADERS.iter().map(|h| h.to_string()).collect(),
+			body_length: None,
 			body_hash: Vec::new(),
+			copied_headers: None,
 			signature: Vec::new(),
+			folding_width: crate::DEFAULT_CNF_FOLDING_WIDTH,
 		};
-		sig.compute_body_hash::<Sha256>(&msg);
+		sig.body_hash = body_hash(MSG_01_RAW, sig.canonicalization);
 		let header_hash = sig.compute_header_hash::<Sha256>(&msg);
 		sig.signature = sig.algorithm.sign(KEY_ED25519, &header_hash).unwrap();
 		assert_eq!(sig.get_header(), ref_sig_header);
@@ -210,22 +467,343 @@ mod tests {
 
 	#[test]
 	fn test_relaxed_relaxed() {
-		let ref_sig_header = "DKIM-Signature: v=1; a=rsa-sha256; k=rsa; c=relaxed/relaxed;\r\n\tt=1681593844; x=1682889844;\r\n\td=example.org;\r\n\ts=dkim-681d955d9fc84d978d71a7d7f8ce7dd6;\r\n\th=Date:From:Resent-Date:Subject:To:cc:reply-to;\r\n\tbh=z85OKVJZHnmg3qFlSpLbpPCZ00irfBdrzQUtabiSl3A=;\r\n\tb=mvdT944tidhpbJHJdbYtIedkXIxNERP7xpAXjWxen5tTULYD72JCQBQy7HbMqix3S0JAex6VVdyqmMjEC053yWNOckVH5E62sGaMyDj8Us1isTmcqIu3VSSQhLpMKkdMv55esqMoaTNC+L+I9p44AHst64sodJmbDA33vLhqoGRja8IylrSK0O4XqgWl2XzGfcyXuDT8miO1NLUU3Hgfgs7edRjBkF2iTMraWiObr0ZW2vI9+Ib9DeuupcC3GMg1MheWlmWNnHlrlSmnkd2VuWEX/ydqlxxXz3/oPjY5ATgYVPBc8apk4KkMDqoQ9EkUvItsvDUz0UhhbXMP3pGGrA==";
+		let ref_sig_header = "DKIM-Signature: v=1; a=rsa-sha256; k=rsa; c=relaxed/relaxed;\r\n\tt=1681593844; x=1682889844;\r\n\td=example.org;\r\n\ts=dkim-681d955d9fc84d978d71a7d7f8ce7dd6;\r\n\th=Date:From:Resent-Date:Subject:To:cc:reply-to;\r\n\tbh=z85OKVJZHnmg3qFlSpLbpPCZ00irfBdrzQUtabiSl3A=;\r\n\tb=mvdT944tidhpbJHJdbYtIedkXIxNERP7xpAXjWxen5tTULYD72JCQBQy7HbMqix3S0JAex6VVdy\r\n\t qmMjEC053yWNOckVH5E62sGaMyDj8Us1isTmcqIu3VSSQhLpMKkdMv55esqMoaTNC+L+I9p44AHs\r\n\t t64sodJmbDA33vLhqoGRja8IylrSK0O4XqgWl2XzGfcyXuDT8miO1NLUU3Hgfgs7edRjBkF2iTMr\r\n\t aWiObr0ZW2vI9+Ib9DeuupcC3GMg1MheWlmWNnHlrlSmnkd2VuWEX/ydqlxxXz3/oPjY5ATgYVPB\r\n\t c8apk4KkMDqoQ9EkUvItsvDUz0UhhbXMP3pGGrA==";
 		let msg = ParsedMessage::from_bytes(MSG_01_RAW).unwrap();
 		let mut sig = Signature {
 			algorithm: Algorithm::Rsa2048Sha256,
 			canonicalization: "relaxed/relaxed".parse().unwrap(),
 			selector: "dkim-681d955d9fc84d978d71a7d7f8ce7dd6".into(),
 			sdid: "example.org".into(),
+			auid: None,
 			timestamp: 1681593844,
 			expiration: Some(1682889844),
 			headers: MSG_01_HEADERS.iter().map(|h| h.to_string()).collect(),
+			body_length: None,
 			body_hash: Vec::new(),
+			copied_headers: None,
 			signature: Vec::new(),
+			folding_width: crate::DEFAULT_CNF_FOLDING_WIDTH,
 		};
-		sig.compute_body_hash::<Sha256>(&msg);
+		sig.body_hash = body_hash(MSG_01_RAW, sig.canonicalization);
 		let header_hash = sig.compute_header_hash::<Sha256>(&msg);
 		sig.signature = sig.algorithm.sign(KEY_RSA2048, &header_hash).unwrap();
 		assert_eq!(sig.get_header(), ref_sig_header);
 	}
+
+	#[test]
+	fn test_auid() {
+		let sig = Signature {
+			algorithm: Algorithm::Ed25519Sha256,
+			canonicalization: "relaxed/relaxed".parse().unwrap(),
+			selector: "dkim-b3fb546a27bb44dd88a1fd2b4b3e2e96".into(),
+			sdid: "example.org".into(),
+			auid: Some("\"john doe\"@news.example.org".into()),
+			timestamp: 1681595158,
+			expiration: None,
+			headers: MSG_01_HEADERS.iter().map(|h| h.to_string()).collect(),
+			body_length: None,
+			body_hash: Vec::new(),
+			copied_headers: None,
+			signature: Vec::new(),
+			folding_width: crate::DEFAULT_CNF_FOLDING_WIDTH,
+		};
+		assert!(sig
+			.get_header()
+			.contains("\r\n\td=example.org;\r\n\ti=\"john=20doe\"@news.example.org;\r\n\ts="));
+	}
+
+	#[test]
+	fn test_body_length() {
+		let mut relaxed_hasher =
+			BodyHasher::new(CanonicalizationType::Relaxed, &[HashAlgorithm::Sha256]);
+		let mut simple_hasher =
+			BodyHasher::new(CanonicalizationType::Simple, &[HashAlgorithm::Sha256]);
+		for line in [&b"..Hello,  World \r"[..], b"\r", b""] {
+			relaxed_hasher.update(line);
+			simple_hasher.update(line);
+		}
+		let (_, relaxed_length) = relaxed_hasher
+			.body_hash("relaxed/relaxed".parse().unwrap(), HashAlgorithm::Sha256)
+			.unwrap();
+		assert_eq!(relaxed_length, b".Hello, World\r\n".len());
+		let (_, simple_length) = simple_hasher
+			.body_hash("simple/simple".parse().unwrap(), HashAlgorithm::Sha256)
+			.unwrap();
+		assert_eq!(simple_length, b".Hello,  World \r\n".len());
+		let mut sig = test_signature(&["From"]);
+		sig.body_length = Some(relaxed_length);
+		assert!(sig
+			.get_header()
+			.contains("\r\n\th=From;\r\n\tl=15;\r\n\tbh="));
+	}
+
+	#[test]
+	fn test_body_hash_sha1() {
+		let canonicalization: Canonicalization = "relaxed/relaxed".parse().unwrap();
+		let mut body_hasher = BodyHasher::new(canonicalization.body_alg(), &[HashAlgorithm::Sha1]);
+		for line in [&b"Hello,  World \r"[..], b"\r", b""] {
+			body_hasher.update(line);
+		}
+		let (body_hash, _) = body_hasher
+			.body_hash(canonicalization, HashAlgorithm::Sha1)
+			.unwrap();
+		assert_eq!(body_hash, Sha1::digest(b"Hello, World\r\n").to_vec());
+		assert!(body_hasher
+			.body_hash(canonicalization, HashAlgorithm::Sha256)
+			.is_err());
+		assert!(body_hasher
+			.body_hash("relaxed/simple".parse().unwrap(), HashAlgorithm::Sha1)
+			.is_err());
+	}
+
+	#[test]
+	fn test_copied_headers() {
+		let msg = ParsedMessage::from_bytes(b"From: John Doe <john.doe@example.org>\r\nSubject: Quarterly report | draft, please review it carefully\r\n before the meeting\r\nTo: jane@example.org\r\n\r\nbody\r\n").unwrap();
+		let mut sig = test_signature(&["From", "Subject", "To"]);
+		sig.copied_headers = Some(get_copied_headers(&sig.headers, &msg));
+		let header = sig.get_header();
+		assert!(header.contains("\r\n\tz=From:John=20Doe=20<john.doe@example.org>|Subject:Quarterly=20report=20=7C\r\n\t =20draft,=20please=20review=20it=20carefully=0D=0A=20before=20the=20meeting|\r\n\t To:jane@example.org;\r\n\tbh="));
+		assert!(header
+			.split("\r\n")
+			.all(|line| line.len() <= crate::DEFAULT_CNF_FOLDING_WIDTH));
+	}
+
+	#[test]
+	fn test_folding() {
+		let mut sig = test_signature(MSG_01_HEADERS);
+		sig.folding_width = 24;
+		sig.body_hash = vec![42; 32];
+		let unsigned_header = sig.get_header();
+		sig.signature = vec![42; 64];
+		let header = sig.get_header();
+		assert!(header
+			.contains("\r\n\th=Date:From:\r\n\t Resent-Date:Subject:\r\n\t To:cc:reply-to;\r\n"));
+		assert!(header
+			.split("\r\n")
+			.skip(4)
+			.all(|line| line.len() <= sig.folding_width));
+		let (prefix, b_value) = header.rsplit_once("b=").unwrap();
+		assert_eq!(format!("{prefix}b="), unsigned_header);
+		assert_eq!(
+			general_purpose::STANDARD
+				.decode(b_value.replace("\r\n\t ", ""))
+				.unwrap(),
+			sig.signature
+		);
+	}
+
+	fn test_signature(headers: &[&str]) -> Signature {
+		Signature {
+			algorithm: Algorithm::Ed25519Sha256,
+			canonicalization: "relaxed/relaxed".parse().unwrap(),
+			selector: "dkim-b3fb546a27bb44dd88a1fd2b4b3e2e96".into(),
+			sdid: "example.org".into(),
+			auid: None,
+			timestamp: 1681595158,
+			expiration: None,
+			headers: headers.iter().map(|h| h.to_string()).collect(),
+			body_length: None,
+			body_hash: Vec::new(),
+			copied_headers: None,
+			signature: Vec::new(),
+			folding_width: crate::DEFAULT_CNF_FOLDING_WIDTH,
+		}
+	}
+
+	#[test]
+	fn test_oversign() {
+		use clap::Parser;
+
+		let raw = b"From: a@example.org\r\nSubject: one\r\nTo: b@example.org\r\nsubject: two\r\n\r\nbody\r\n";
+		let msg = ParsedMessage::from_bytes(raw).unwrap();
+		let cnf = Config::try_parse_from([
+			"filter-dkimout",
+			"-h",
+			"from",
+			"-h",
+			"to",
+			"-o",
+			"date",
+			"--header-oversign",
+			"subject",
+			"--header-oversign",
+			"cc",
+			"--header-oversign",
+			"from",
+		])
+		.unwrap();
+		assert_eq!(
+			get_headers(&cnf, "example.org", &msg),
+			vec!["From", "From", "Subject", "Subject", "Subject", "To", "cc"]
+		);
+	}
+
+	#[test]
+	fn test_bottom_up() {
+		let msg = ParsedMessage::from_bytes(
+			b"Subject: one\r\nFrom: a@example.org\r\nSubject: two\r\n\r\nbody\r\n",
+		)
+		.unwrap();
+		let bottom = ParsedMessage::from_bytes(b"Subject: two\r\n\r\nbody\r\n").unwrap();
+		let both =
+			ParsedMessage::from_bytes(b"Subject: one\r\nSubject: two\r\n\r\nbody\r\n").unwrap();
+		assert_eq!(
+			test_signature(&["Subject"]).compute_header_hash::<Sha256>(&msg),
+			test_signature(&["Subject"]).compute_header_hash::<Sha256>(&bottom)
+		);
+		assert_eq!(
+			test_signature(&["Subject", "Subject", "Subject"]).compute_header_hash::<Sha256>(&msg),
+			test_signature(&["Subject", "Subject", "Subject"]).compute_header_hash::<Sha256>(&both)
+		);
+	}
+
+	#[test]
+	fn test_repeated_headers() {
+		use clap::Parser;
+
+		let msg = ParsedMessage::from_bytes(MSG_02_RAW).unwrap();
+		let cnf = Config::try_parse_from([
+			"filter-dkimout",
+			"-h",
+			"from",
+			"-h",
+			"received",
+			"-o",
+			"resent-from",
+			"-o",
+			"resent-date",
+			"--header-oversign",
+			"subject",
+		])
+		.unwrap();
+		assert_eq!(
+			get_headers(&cnf, "example.org", &msg),
+			vec![
+				"From",
+				"Received",
+				"Received",
+				"Received",
+				"Resent-Date",
+				"Resent-Date",
+				"Resent-From",
+				"Resent-From",
+				"Subject",
+				"Subject",
+			]
+		);
+	}
+
+	#[test]
+	fn test_repeated_headers_hash() {
+		let msg = ParsedMessage::from_bytes(MSG_02_RAW).unwrap();
+		let first = ParsedMessage::from_bytes(
+			b"Resent-From: First Resender <resent.first@example.org>\r\n\r\nbody\r\n",
+		)
+		.unwrap();
+		let both = ParsedMessage::from_bytes(b"Resent-From: Second Resender <resent.second@example.org>\r\nResent-From: First Resender <resent.first@example.org>\r\n\r\nbody\r\n").unwrap();
+		assert_eq!(
+			test_signature(&["Resent-From"]).compute_header_hash::<Sha256>(&msg),
+			test_signature(&["Resent-From"]).compute_header_hash::<Sha256>(&first)
+		);
+		assert_eq!(
+			test_signature(&["Resent-From", "Resent-From"]).compute_header_hash::<Sha256>(&msg),
+			test_signature(&["Resent-From", "Resent-From"]).compute_header_hash::<Sha256>(&both)
+		);
+		assert_ne!(
+			test_signature(&["Resent-From", "Resent-From"]).compute_header_hash::<Sha256>(&msg),
+			test_signature(&["Resent-From", "Resent-From"]).compute_header_hash::<Sha256>(&first)
+		);
+	}
+
+	async fn selectors(db: &SqlitePool) -> Vec<String> {
+		sqlx::query_as::<_, (String,)>("SELECT selector FROM key_db ORDER BY usable_after")
+			.fetch_all(db)
+			.await
+			.unwrap()
+			.into_iter()
+			.map(|(selector,)| selector)
+			.collect()
+	}
+
+	async fn generate_key(db: &SqlitePool, publish_delay: &str) {
+		let cnf = Config::from_args(&["-u", "builtin:none", "-w", publish_delay]);
+		crate::key::generate_key(db, &cnf, "example.org", Algorithm::Ed25519Sha256)
+			.await
+			.unwrap();
+	}
+
+	async fn signing_selector(db: &SqlitePool) -> Result<String> {
+		// A pending key must be reported at once instead of being waited for.
+		let res = tokio::time::timeout(
+			Duration::from_secs(1),
+			get_db_data(db, "example.org", Algorithm::Ed25519Sha256),
+		)
+		.await
+		.unwrap();
+		res.map(|(selector, _)| selector)
+	}
+
+	#[tokio::test]
+	async fn pending_key_not_used() {
+		let db = crate::db::init_test_db().await;
+		generate_key(&db, "3600").await;
+		assert!(signing_selector(&db).await.is_err());
+	}
+
+	#[tokio::test]
+	async fn usable_key_used() {
+		let db = crate::db::init_test_db().await;
+		generate_key(&db, "0").await;
+		assert_eq!(
+			signing_selector(&db).await.unwrap(),
+			selectors(&db).await[0]
+		);
+	}
+
+	#[tokio::test]
+	async fn rotation_keeps_old_key() {
+		let db = crate::db::init_test_db().await;
+		generate_key(&db, "0").await;
+		generate_key(&db, "3600").await;
+		let selectors = selectors(&db).await;
+		// The old key ends its cryptoperiod before the new one.
+		sqlx::query("UPDATE key_db SET not_after = not_after - 86400 WHERE selector = $1")
+			.bind(&selectors[0])
+			.execute(&db)
+			.await
+			.unwrap();
+		assert_eq!(signing_selector(&db).await.unwrap(), selectors[0]);
+		sqlx::query("UPDATE key_db SET usable_after = unixepoch() WHERE selector = $1")
+			.bind(&selectors[1])
+			.execute(&db)
+			.await
+			.unwrap();
+		assert_eq!(signing_selector(&db).await.unwrap(), selectors[1]);
+	}
+
+	#[tokio::test]
+	async fn expired_key_fallback() {
+		let db = crate::db::init_test_db().await;
+		generate_key(&db, "0").await;
+		let selector = selectors(&db).await.remove(0);
+		sqlx::query("UPDATE key_db SET not_after = unixepoch() - 60 WHERE selector = $1")
+			.bind(&selector)
+			.execute(&db)
+			.await
+			.unwrap();
+		assert_eq!(signing_selector(&db).await.unwrap(), selector);
+		sqlx::query("UPDATE key_db SET revocation = unixepoch() WHERE selector = $1")
+			.bind(&selector)
+			.execute(&db)
+			.await
+			.unwrap();
+		let res: Option<(String, String, i64)> =
+			sqlx::query_as(crate::db::SELECT_LATEST_SIGNING_KEY)
+				.bind("example.org")
+				.bind(Algorithm::Ed25519Sha256.to_string())
+				.fetch_optional(&db)
+				.await
+				.unwrap();
+		assert!(res.is_none());
+	}
 }
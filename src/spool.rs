@@ -0,0 +1,151 @@
+use anyhow::Result;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::PathBuf;
+use tokio::io::{AsyncBufRead, BufReader};
+use uuid::Uuid;
+
+/// Keeps the body lines of a message so they can be returned once the message has been signed.
+/// Lines are kept in memory until the threshold is reached, they are then written to a
+/// temporary file.
+#[derive(Debug)]
+pub struct Spool {
+	threshold: usize,
+	content: SpoolContent,
+}
+
+#[derive(Debug)]
+enum SpoolContent {
+	Memory(Vec<u8>),
+	File(Box<SpoolFile>),
+}
+
+#[derive(Debug)]
+struct SpoolFile {
+	path: PathBuf,
+	writer: BufWriter<File>,
+}
+
+impl Drop for SpoolFile {
+	fn drop(&mut self) {
+		if let Err(err) = std::fs::remove_file(&self.path) {
+			log::error!("{}: {err}", self.path.display());
+		}
+	}
+}
+
+impl Spool {
+	pub fn new(threshold: usize) -> Self {
+		Self {
+			threshold,
+			content: SpoolContent::Memory(Vec::new()),
+		}
+	}
+
+	pub fn write_line(&mut self, line: &[u8]) -> Result<()> {
+		if let SpoolContent::Memory(buff) = &self.content {
+			if buff.len() + line.len() + 1 > self.threshold {
+				self.content = SpoolContent::File(Box::new(spill(buff)?));
+			}
+		}
+		match &mut self.content {
+			SpoolContent::Memory(buff) => {
+				buff.extend_from_slice(line);
+				buff.push(b'\n');
+			}
+			SpoolContent::File(f) => {
+				f.writer.write_all(line)?;
+				f.writer.write_all(b"\n")?;
+			}
+		}
+		Ok(())
+	}
+
+	pub fn flush(&mut self) -> Result<()> {
+		if let SpoolContent::File(f) = &mut self.content {
+			f.writer.flush()?;
+		}
+		Ok(())
+	}
+
+	/// Returns a reader on the lines written so far, each of them followed by a line feed. The
+	/// spool must have been flushed beforehand.
+	pub async fn reader(&self) -> Result<Box<dyn AsyncBufRead + Unpin + '_>> {
+		match &self.content {
+			SpoolContent::Memory(buff) => Ok(Box::new(buff.as_slice())),
+			SpoolContent::File(f) => {
+				let file = tokio::fs::File::open(&f.path).await?;
+				Ok(Box::new(BufReader::new(file)))
+			}
+		}
+	}
+}
+
+// The file is written synchronously: lines are buffered and this avoids having to turn every
+// new line into a future in the main loop.
+fn spill(content: &[u8]) -> Result<SpoolFile> {
+	let path = std::env::temp_dir().join(format!(
+		"{}-{}.spool",
+		env!("CARGO_PKG_NAME"),
+		Uuid::new_v4().simple()
+	));
+	let file = OpenOptions::new()
+		.write(true)
+		.create_new(true)
+		.mode(0o600)
+		.open(&path)
+		.map_err(|e| anyhow::anyhow!("{}: {e}", path.display()))?;
+	let mut writer = BufWriter::new(file);
+	writer.write_all(content)?;
+	log::debug!("{}: message body spilled to disk", path.display());
+	Ok(SpoolFile { path, writer })
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use tokio::io::AsyncBufReadExt;
+
+	async fn read_lines(spool: &mut Spool) -> Vec<Vec<u8>> {
+		spool.flush().unwrap();
+		let mut lines = spool.reader().await.unwrap().split(b'\n');
+		let mut ret = Vec::new();
+		while let Some(line) = lines.next_segment().await.unwrap() {
+			ret.push(line);
+		}
+		ret
+	}
+
+	#[tokio::test]
+	async fn memory() {
+		let mut spool = Spool::new(1024);
+		spool.write_line(b"Hello").unwrap();
+		spool.write_line(b"").unwrap();
+		spool.write_line(b"World!\r").unwrap();
+		assert!(matches!(spool.content, SpoolContent::Memory(_)));
+		assert_eq!(
+			read_lines(&mut spool).await,
+			vec![b"Hello".to_vec(), b"".to_vec(), b"World!\r".to_vec()]
+		);
+	}
+
+	#[tokio::test]
+	async fn file() {
+		let mut spool = Spool::new(8);
+		spool.write_line(b"Hello").unwrap();
+		assert!(matches!(spool.content, SpoolContent::Memory(_)));
+		spool.write_line(b"World!").unwrap();
+		let path = match &spool.content {
+			SpoolContent::File(f) => f.path.clone(),
+			SpoolContent::Memory(_) => panic!("the spool should be a file"),
+		};
+		assert!(path.exists());
+		assert_eq!(
+			read_lines(&mut spool).await,
+			vec![b"Hello".to_vec(), b"World!".to_vec()]
+		);
+		drop(spool);
+		assert!(!path.exists());
+	}
+}
@@ -0,0 +1,196 @@
+use crate::algorithm::Algorithm;
+use crate::config::Config;
+use anyhow::{anyhow, Result};
+use sqlx::SqlitePool;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+
+pub const ENV_ZONE_FILE: &str = "DKIMOUT_ZONE_FILE";
+const TXT_STRING_MAX_LEN: usize = 255;
+const ZONE_FILE_EXT: &str = "dkim.zone";
+
+pub async fn update(db: &SqlitePool, cnf: &Config, sdid: &str) -> Result<()> {
+	let dir = cnf
+		.zone_file_dir()
+		.ok_or(anyhow!("the zone file directory is not configured"))?;
+	let res: Vec<(String, String, String)> = sqlx::query_as(crate::db::SELECT_ZONE_KEYS)
+		.bind(sdid)
+		.fetch_all(db)
+		.await?;
+	let mut content = format!(
+		"; DKIM records for {sdid}\n; Generated by {}, do not edit.\n",
+		env!("CARGO_PKG_NAME")
+	);
+	for (selector, algorithm, public_key) in res {
+		let algorithm = algorithm.parse::<Algorithm>().map_err(|e| anyhow!(e))?;
+		let name = crate::dns::record_name(&selector, sdid);
+		let txt = crate::dns::txt_record(cnf.txt_record_tags(), algorithm, &public_key);
+		content += &format_record(&name, &txt);
+	}
+	let path = zone_file_path(dir, sdid);
+	// The marker is only removed once the reload command succeeded, so a failed reload is run
+	// again during the next update even though the zone file is already up to date.
+	let reload_marker = hidden_path(&path, ".reload")?;
+	if fs::read_to_string(&path).await.ok().as_deref() == Some(content.as_str()) {
+		if !reload_marker.exists() {
+			log::debug!("{}: zone file is up to date", path.display());
+			return Ok(());
+		}
+	} else {
+		if cnf.zone_file_reload_cmd().is_some() {
+			write_atomic(&reload_marker, "").await?;
+		}
+		write_atomic(&path, &content).await?;
+		log::info!("{}: zone file updated", path.display());
+	}
+	if let Some(cmd) = cnf.zone_file_reload_cmd() {
+		let path = path.display().to_string();
+		let envs = [(crate::dns::ENV_SDID, sdid), (ENV_ZONE_FILE, path.as_str())];
+		crate::dns::run_shell_cmd(cmd, sdid, "zone reload command", &envs).await?;
+		if let Err(err) = fs::remove_file(&reload_marker).await {
+			if err.kind() != ErrorKind::NotFound {
+				return Err(anyhow!("{}: {err}", reload_marker.display()));
+			}
+		}
+	}
+	Ok(())
+}
+
+fn zone_file_path(dir: &Path, sdid: &str) -> PathBuf {
+	dir.join(format!("{sdid}.{ZONE_FILE_EXT}"))
+}
+
+fn hidden_path(path: &Path, ext: &str) -> Result<PathBuf> {
+	let file_name = path
+		.file_name()
+		.ok_or(anyhow!("{}: invalid file name", path.display()))?;
+	let mut name = std::ffi::OsString::from(".");
+	name.push(file_name);
+	name.push(ext);
+	Ok(path.with_file_name(name))
+}
+
+async fn write_atomic(path: &Path, content: &str) -> Result<()> {
+	let tmp_path = hidden_path(path, ".tmp")?;
+	let mut file = fs::File::create(&tmp_path)
+		.await
+		.map_err(|e| anyhow!("{}: {e}", tmp_path.display()))?;
+	file.write_all(content.as_bytes())
+		.await
+		.map_err(|e| anyhow!("{}: {e}", tmp_path.display()))?;
+	file.sync_all()
+		.await
+		.map_err(|e| anyhow!("{}: {e}", tmp_path.display()))?;
+	drop(file);
+	fs::rename(&tmp_path, path)
+		.await
+		.map_err(|e| anyhow!("{}: {e}", path.display()))?;
+	// The rename itself is only durable once the directory has been synced.
+	let dir = match path.parent() {
+		Some(dir) if !dir.as_os_str().is_empty() => dir,
+		_ => Path::new("."),
+	};
+	fs::File::open(dir)
+		.await
+		.map_err(|e| anyhow!("{}: {e}", dir.display()))?
+		.sync_all()
+		.await
+		.map_err(|e| anyhow!("{}: {e}", dir.display()))?;
+	Ok(())
+}
+
+pub fn format_record(name: &str, txt: &str) -> String {
+	let strings = txt
+		.as_bytes()
+		.chunks(TXT_STRING_MAX_LEN)
+		.map(|c| format!("\t\"{}\"", escape(&String::from_utf8_lossy(c))))
+		.collect::<Vec<String>>()
+		.join("\n");
+	format!(
+		"{name}.\t{}\tIN\tTXT\t(\n{strings}\n)\n",
+		crate::DNS_RECORD_TTL
+	)
+}
+
+// RFC 1035, section 5.1: inside a quoted character string, quotes and backslashes must be
+// escaped.
+fn escape(s: &str) -> String {
+	s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[tokio::test]
+	async fn reload_retry() {
+		let dir = std::env::temp_dir().join(format!("dkimout-{}", uuid::Uuid::new_v4().simple()));
+		fs::create_dir(&dir).await.unwrap();
+		let dir_arg = dir.display().to_string();
+		let db = crate::db::init_test_db().await;
+		crate::key::generate_key(
+			&db,
+			&Config::from_args(&["-u", "builtin:none", "-d", "example.org"]),
+			"example.org",
+			Algorithm::Ed25519Sha256,
+		)
+		.await
+		.unwrap();
+		let zone_path = zone_file_path(&dir, "example.org");
+		let failing_cnf = Config::from_args(&[
+			"--zone-file-dir",
+			&dir_arg,
+			"--zone-file-reload-cmd",
+			"false",
+		]);
+		let cnf = Config::from_args(&[
+			"--zone-file-dir",
+			&dir_arg,
+			"--zone-file-reload-cmd",
+			"touch \"$DKIMOUT_ZONE_FILE.reloaded\"",
+		]);
+		let reloaded = zone_path.with_extension("zone.reloaded");
+
+		assert!(update(&db, &failing_cnf, "example.org").await.is_err());
+		assert!(zone_path.exists());
+		update(&db, &cnf, "example.org").await.unwrap();
+		assert!(reloaded.exists());
+		fs::remove_file(&reloaded).await.unwrap();
+		update(&db, &cnf, "example.org").await.unwrap();
+		assert!(!reloaded.exists());
+		fs::remove_dir_all(&dir).await.unwrap();
+	}
+
+	#[test]
+	fn short_record() {
+		let record = format_record("dkim._domainkey.example.org", "v=DKIM1; k=ed25519; p=test");
+		assert_eq!(
+			record,
+			"dkim._domainkey.example.org.\t3600\tIN\tTXT\t(\n\t\"v=DKIM1; k=ed25519; p=test\"\n)\n"
+		);
+	}
+
+	#[test]
+	fn escaped_record() {
+		let cnf = Config::from_args(&["--dns-notes", "Contact \"postmaster\" \\ abuse"]);
+		let txt = crate::dns::txt_record(cnf.txt_record_tags(), Algorithm::Ed25519Sha256, "test");
+		let record = format_record("dkim._domainkey.example.org", &txt);
+		assert_eq!(
+			record,
+			"dkim._domainkey.example.org.\t3600\tIN\tTXT\t(\n\t\"v=DKIM1; k=ed25519; n=Contact=20\\\"postmaster\\\"=20\\\\=20abuse; p=test\"\n)\n"
+		);
+	}
+
+	#[test]
+	fn long_record() {
+		let txt = format!("v=DKIM1; k=rsa; p={}", "A".repeat(400));
+		let record = format_record("dkim._domainkey.example.org", &txt);
+		let lines: Vec<&str> = record.lines().collect();
+		assert_eq!(lines.len(), 4);
+		assert_eq!(lines[1].len(), TXT_STRING_MAX_LEN + 3);
+		assert_eq!(lines[2], format!("\t\"{}\"", "A".repeat(163)));
+		assert_eq!(lines[3], ")");
+	}
+}
@@ -0,0 +1,28 @@
+-- Key database used by start_test.py, load it with: sqlite3 key-db.sqlite3 < key-db.sql
+-- The keys are valid until 2123 and revoked two days later (the default revocation delay).
+BEGIN TRANSACTION;
+CREATE TABLE _sqlx_migrations (
+    version BIGINT PRIMARY KEY,
+    description TEXT NOT NULL,
+    installed_on TIMESTAMP NOT NULL DEFAULT CURRENT_TIMESTAMP,
+    success BOOLEAN NOT NULL,
+    checksum BLOB NOT NULL,
+    execution_time BIGINT NOT NULL
+);
+INSERT INTO _sqlx_migrations VALUES(20230409141801,'initial','2023-04-23 17:17:28',1,X'373c3991eefd18e9e12bf5283373b9db63cddbe4313deb8bcbef41005a0c2fe50a3aaf9b97e69f31a566e6e834f417c0',58964221);
+CREATE TABLE key_db (
+	selector		TEXT,
+	sdid			TEXT,
+	algorithm		TEXT,
+	creation		INTEGER,
+	not_after		INTEGER,
+	revocation		INTEGER,
+	published		BOOLEAN,
+	private_key		TEXT,
+	public_key		TEXT
+);
+INSERT INTO key_db VALUES('dkim-d60b5d2b1fdc465f83bb8c16ee1ef1eb','example.com','rsa2048-sha256',1682270642,4837944242,4839672242,0,'MIIEvgIBADANBgkqhkiG9w0BAQEFAASCBKgwggSkAgEAAoIBAQC8/fXW/FZYXqFkp2siXaTB2/H+77lvoxsy1Ram1TCnnPZ2VrXN2dEEX86vWX6c6Ht+ZtFg3BufsqbOaPJkNm/rwKuKr1/TC+Y0eXFsP3bv8HZvchmfhzrRa20sLtCSuUBSMFeeqKfQ7J30lpRS5YOMbk9kpZudeFkHefDKVamyIVMwjXq2J6dDfKKAdqQ9Gs+YyibVMo36IXiF5OBPom0jb7V7olfe5FBzwH/ZZQwIr1r6F2IgmZssbgxlHqt7qrqR/x3sufbCOe13VSBionSuBL5FAoNUgGEhYgGC4p6PZpryk3nE9/GpRa9hB4Htf5g6pMdyMzYsQ2khCj0RbdFFAgMBAAECggEAQjdw1TxgPGIbhhqqjMXmng2kAkCX5idttie24gk8HN3/9IJ4/IDenPu8fO+MTwmdizIXFirIevGrRBK9KqNwH1fIQrUji9xVcflC5jxuPa8wr+d4Vs6YqxtKivp0FZRWlvVdHbfV5Hui9Dl2Cn1j0+YqZwcnOMsb1mTBGwNci45Kytbf0cLS0m8227Y+UqUZIGLsShvEjvvQJ86QxbgVRq+iWRMJrcXg8tFd+WYmMVENwZwmq+jtdK74yok95x8HcW6ibSSICQo9ZkyjWN22AvJsFfojth88RLvFDtNB1a35N9sI0gV5laYLN5o28Kus+2LIZ2f4pBrmAC/c/friAQKBgQDwaFnOETSApH6jrMi4LrQHz/betlU7uFxd41E6H0DT5rxpLmjMEVeDt6NKQRNWaXQG3MSTxtEl4z2/+NKoidsB1DSRsLuNuHpvkGw8mnfGgzIZYnVrOEQStvRPJGlBir0jJFjiZmdHU44q39Tws1Ii1cm6Ka3kBCjhOJMCDfGWQQKBgQDJP+wGRiezN2WIFMx4NbJOJ/3LwTapKj3gxtja1KlGTaimiFfF/axjNtwGOeMSobcXWIwV8q7SppbI3z3CJLTp26tVI6JOwPzOPBBzKWUuA9dy6U4KK9sPyCU1j+eS1Z2vTnXOdIwe++Ivjene5xEwXuehUM+0n1tfF1ZInfFiBQKBgQDCNKoHa6KsITYkZponH6NThE57j3O/MKl9NvsRJtop1AV0OodyArhdsWV5wKQsdDfEq2cpsbyXDMF/aTRT7jk7ev+Uid6ZF+PgaBznwCjt9mHsW7tbCDMFo0ItBM76oWc1ElGzYuIfnoIEZJkScOnzNh0mBDFzN2EGv7sSN81ngQKBgEf5EmIUCPKxJyCJv65mFcSZzMxn5zKMpuCSfM+WKou4Dk6wiCOI23QIFJwta6YoK6CvzDm1NwyjA14HaMDb+89+/kFOoydjIYZoOMHW1hakzRKNmZlNoeR9AcGsG3eJ6gDeIIebatlR38x+Blvva1hPOKuaiQ3hx/20DOmfgso5AoGBAIdt4m6Jxjh/+XQCX6pHMG53Hjc8hkc9bOFOdDXUC42q10kHPwwC/CMXJhjg8ngERkTS3taulxiHaPtsxlfuch/QuNgq7X8XRIn3AVg2OVTqutZvpELHVbT9bvFCk9Q9KpI2gKk3P0K3QfF4J8RrcaA/j1M10adb0I3+AJPvvTuC','MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAvP311vxWWF6hZKdrIl2kwdvx/u+5b6MbMtUWptUwp5z2dla1zdnRBF/Or1l+nOh7fmbRYNwbn7KmzmjyZDZv68Criq9f0wvmNHlxbD927/B2b3IZn4c60WttLC7QkrlAUjBXnqin0Oyd9JaUUuWDjG5PZKWbnXhZB3nwylWpsiFTMI16tienQ3yigHakPRrPmMom1TKN+iF4heTgT6JtI2+1e6JX3uRQc8B/2WUMCK9a+hdiIJmbLG4MZR6re6q6kf8d7Ln2wjntd1UgYqJ0rgS+RQKDVIBhIWIBguKej2aa8pN5xPfxqUWvYQeB7X+YOqTHcjM2LENpIQo9EW3RRQIDAQAB');
+INSERT INTO key_db VALUES('dkim-4198e1e5f78f4372875eccadfb7b6343','example.org','rsa2048-sha256',1682270642,4837944242,4839672242,0,'MIIEvAIBADANBgkqhkiG9w0BAQEFAASCBKYwggSiAgEAAoIBAQCw1kdTFS8GYv3ctGwiLvqJtps83DiIBi1Bl9vQ34gDNdQd4KBcnkXOYFTPLS4Pdow2ny94RPzLZShMaDJ0x64/4bIMNdunzi7KtLbkMf5IobQJ+mRJyBtr2eBKbC1U7T3Gld2lTcbddqXDY6C6S9aCAyQEcHH58YIzpDNNW4p0vhoyXGCRT6duu4Y+UXlKmm4wICsBquRPurJ0rj0lxFxHlfWw3nG3dkxj0fiQ/khfdCZtqCYfcZnrBReI82/tEcFe4lmHj0fagWMJOWayVJLvS6rqFrU2mIS//78PRM1hXJtU5u7TRurEZ1HpENiA7iBvMCWD0WrQDZN8/frQNMeZAgMBAAECggEACMAhuWfD8vH1E/XNoAdpGGVz2xgeqAR+Os+z7esRxO7l9zCFXu/qDmNP7wySSeuY1p8b7zyiscHos5c4X/T9l2WotNf5bZ1TTW6R2krrBVcTHKdKTShcywJ2Cdi+iYRP2ULTO/nUTGMxorCMZIuaKsbTAnE6Ap9DzSBj9YEBIfOtMroPdD0wNPYjbU5x1coTgHTVAqJ9vignc85lCyAz0S6XmjeY2/2u1FAtoUnu60wteTinpQerWqlRMNZq7KBu7dCW40jwRq+0vEijlNOam/fZqTSJL00rN/M503sHH6zqk/YjLD2Jq+tm2BJ2r67ipamN3YTZAXYNpZy1SPnVgQKBgQDYaJaHoH7ikdPrP6LnWUG+6IW4ITL677SBooATPAE//d2oPMJvZ01hi1KipmGESfH71dSk6Tet3fGV14t4sSg3m9eErv8iUeqcvUjRklY5JCWofxZG5KAcwoxGT3kEU6hZIF7K6WflWIBq/HZZQqXG7SNVjk3uUU7YNxmCNWqpCQKBgQDRMF+bWCzbHKkODZyPjl0VEb2KNLxmrfIDWupJrz+uX8pli4ni7YWbMkAwTfPRzR0QMiRtlLEXC/87G+0TfaPy466nYwlGKkIKGzRm6KR7xW23LzqoRkJpAcH0eF4XTkX14W5lPtUpJRZxMDrrsbnCYALnPYdkULgDXveVIGyeEQKBgAY81YHpRV8AdwxD1E04zGVVTG1nP5HuENTfuzNSunJ+vcHYpn4RizBxlJstkof+naP1oOBz/G/dgtYf1VxQNumLa2XeqWny1IFaD1xDDIsvGIrHxvuaIWdS0DpCliliw+p1vWW53o3ExEbRKhQekKqwMSGJLYPeAhjHVcvkAEQJAoGAQlQAPJVcw6z2vYn7VllhkkipvKS0CCqnTgEJwItcucGNv1zbP6TodCkeyTIuWRucV4JOoqA+9nvLVcapCUERNvaSbmisjB7iCapOl0K0xLn469iB1NFSRVFVWlrAGsuqyyfLhTbDB+TGlOUiCZG0wLkIsqGDyH7/mbIG9rXtOsECgYBLjonTvaP4G55TzTssoy1MTxZzafe4cI8Qhf3DmLePqz56yOkBudjhuzvZrbjYxXNPxH6Yz+SPd1jg0JOjlXKfc0VCeglAB4VMTCyYglOOPPAoRMJp8J6/ePYe21oy/9oHxmQYUWVweX91GvyLZfi4w/vUtOttTovDrHYddd00Lg==','MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAsNZHUxUvBmL93LRsIi76ibabPNw4iAYtQZfb0N+IAzXUHeCgXJ5FzmBUzy0uD3aMNp8veET8y2UoTGgydMeuP+GyDDXbp84uyrS25DH+SKG0CfpkScgba9ngSmwtVO09xpXdpU3G3Xalw2OgukvWggMkBHBx+fGCM6QzTVuKdL4aMlxgkU+nbruGPlF5SppuMCArAarkT7qydK49JcRcR5X1sN5xt3ZMY9H4kP5IX3QmbagmH3GZ6wUXiPNv7RHBXuJZh49H2oFjCTlmslSS70uq6ha1NpiEv/+/D0TNYVybVObu00bqxGdR6RDYgO4gbzAlg9Fq0A2TfP360DTHmQIDAQAB');
+INSERT INTO key_db VALUES('dkim-66bc7a1ab99d4761958d971e29756522','example.com','ed25519-sha256',1682270642,4837944242,4839672242,0,'MfFU4C2Fk8avBySxgFtDk0kPDDYtRCW3ALBhVG74O8A=','cBT5lY3GNiXpEMOxCj4KVADCKDdNpaBJkpVpy32tLB4=');
+INSERT INTO key_db VALUES('dkim-b25c49523266487092c466afb1b8848e','example.org','ed25519-sha256',1682270642,4837944242,4839672242,0,'/B+S6WjtytAuZe6C1PTbLZsLbgKM5VunQfH3p2qqSVI=','fY5GFIbuT+MmRY/2V2Z4yEO2vYljxSuyWfm4LJtFXzA=');
+COMMIT;
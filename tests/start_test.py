@@ -16,7 +16,7 @@ import time
 
 ADDR_FROM = "test.from@example.org"
 ADDR_TO = "test@example.com"
-DB_NAME = "key-db.sqlite3"
+DB_SCRIPT = "key-db.sql"
 DEFAULT_PORT = 2525
 
 
@@ -38,11 +38,24 @@ def cp_tmp_file(path, executable=False):
     return file
 
 
+def load_key_db(con):
+    script = pathlib.Path(__file__).parent.resolve() / DB_SCRIPT
+    with open(script) as f:
+        con.executescript(f.read())
+    return con
+
+
+def create_tmp_db():
+    file = tempfile.NamedTemporaryFile(suffix="-key-db.sqlite3", delete=False)
+    file.close()
+    load_key_db(sqlite3.connect(file.name)).close()
+    return file
+
+
 def get_cmd_filter_dkimout(test_dir, algorithm, canonicalization, target):
     filter_path = test_dir.parent / "target" / target / "filter-dkimout"
     filter_path = cp_tmp_file(filter_path, executable=True).name
-    db_path = test_dir / DB_NAME
-    db_path = cp_tmp_file(db_path).name
+    db_path = create_tmp_db().name
     return (
         filter_path,
         db_path,
@@ -86,7 +99,6 @@ def send_msg(smtp, msg_file):
 
 
 def custom_get_txt(name, timeout=5):
-    db_path = pathlib.Path(__file__).parent.resolve() / DB_NAME
     algs_assoc = {
         "ed25519-sha256": "ed25519",
         "rsa2048-sha256": "rsa",
@@ -97,7 +109,7 @@ def custom_get_txt(name, timeout=5):
     selector, domain = name.split("._domainkey.")
     if domain.endswith("."):
         domain = domain[:-1]
-    con = sqlite3.connect(db_path)
+    con = load_key_db(sqlite3.connect(":memory:"))
     cur = con.cursor()
     res = cur.execute(
         "SELECT algorithm, public_key FROM key_db WHERE selector = ? AND sdid = ? LIMIT 1",